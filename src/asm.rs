@@ -0,0 +1,589 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use chip8::{MEMORY_SIZE, PROGRAM_START};
+use instruction::Instruction;
+
+/// An assembler error with the 1 based line it occurred on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+/// Assembles classic CHIP-8 mnemonics into a ROM to be loaded at 0x200.
+///
+/// The syntax is the one the disassembler prints:
+///
+/// ```text
+/// ; comments start with a semicolon
+/// SPEED   EQU 2           ; constants, `SPEED = 2` works as well
+/// start:  LD I, ball      ; labels end with a colon
+///         DRW V0, V1, 1
+///         ADD V0, SPEED
+///         JP start
+/// ball:   DB 0x80         ; DB and DW emit bytes and big endian words
+/// ```
+///
+/// Numbers are decimal, `0x`/`$` hexadecimal or `0b`/`%` binary, operands
+/// may add and subtract numbers, constants and labels. `ORG` moves the
+/// output to a later address, the gap is filled with zeros.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut symbols = HashMap::new();
+    let mut statements = Vec::new();
+    let mut address = PROGRAM_START as u32;
+
+    //first pass: collect labels and constants and size every statement
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let error = |message: String| AsmError { line, message };
+
+        let mut rest = strip_comment(text).trim();
+
+        if let Some(colon) = label_end(rest) {
+            let label = rest[..colon].trim();
+            define(&mut symbols, label, address as i64).map_err(error)?;
+            rest = rest[colon + 1..].trim();
+        }
+
+        if rest.is_empty() {
+            continue;
+        }
+
+        if let Some((name, value)) = constant(rest) {
+            let value = sum(value, &symbols).map_err(error)?;
+            define(&mut symbols, name, value).map_err(error)?;
+            continue;
+        }
+
+        let (mnemonic, operands) = split_statement(rest);
+        let operands = split_operands(operands);
+
+        let mnemonic = mnemonic.to_uppercase();
+        let size = match mnemonic.as_str() {
+            "ORG" => {
+                let target = evaluate(single(&operands).map_err(error)?, &symbols, Width::Any).map_err(error)?;
+                if target < address {
+                    return Err(error(format!("ORG 0x{:03X} is before the current address 0x{:03X}", target, address)));
+                }
+                target - address
+            }
+            "DB" => operands.iter().map(|op| string_literal(op).map_or(1, |s| s.len() as u32)).sum(),
+            "DW" => 2 * operands.len() as u32,
+            _ => 2,
+        };
+
+        statements.push(Statement { line, address, mnemonic, operands });
+        address += size;
+
+        if address > MEMORY_SIZE as u32 {
+            return Err(error("program does not fit into memory".to_string()));
+        }
+    }
+
+    //second pass: everything is known, emit the bytes
+    let mut rom = Vec::new();
+    for statement in &statements {
+        let error = |message: String| AsmError { line: statement.line, message };
+
+        let fill = statement.address as usize - PROGRAM_START;
+        rom.resize(fill, 0);
+
+        match statement.mnemonic.as_str() {
+            "ORG" => (),
+            "DB" => for operand in &statement.operands {
+                match string_literal(operand) {
+                    Some(text) => rom.extend_from_slice(text.as_bytes()),
+                    None => {
+                        let value = evaluate(operand, &symbols, Width::Byte).map_err(error)?;
+                        rom.push(value as u8);
+                    }
+                }
+            },
+            "DW" => for operand in &statement.operands {
+                let value = evaluate(operand, &symbols, Width::Word).map_err(error)?;
+                rom.push((value >> 8) as u8);
+                rom.push(value as u8);
+            },
+            _ => {
+                let instruction = encode(&statement.mnemonic, &statement.operands, &symbols).map_err(error)?;
+                let opcode = instruction.encode();
+                rom.push((opcode >> 8) as u8);
+                rom.push(opcode as u8);
+            }
+        }
+    }
+
+    Ok(rom)
+}
+
+struct Statement {
+    line: usize,
+    address: u32,
+    mnemonic: String,
+    operands: Vec<String>,
+}
+
+#[derive(Clone, Copy)]
+enum Operand<'a> {
+    Register(u8),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    B,
+    /// An expression, evaluated once the instruction says how wide it is.
+    Value(&'a str),
+}
+
+/// The width of an operand, negative values wrap around to it.
+#[derive(Clone, Copy)]
+enum Width {
+    /// DRW's sprite height, 0 to 15.
+    Nibble,
+    Byte,
+    Word,
+    /// 12 bit addresses, which can not be negative.
+    Address,
+    /// ORG targets, any value that is not negative.
+    Any,
+}
+
+fn encode(mnemonic: &str, operands: &[String], symbols: &HashMap<String, i64>) -> Result<Instruction, String> {
+    let ops: Vec<Operand> = operands.iter().map(|op| operand(op)).collect();
+    let byte = |text| evaluate(text, symbols, Width::Byte).map(|value| value as u8);
+    let address = |text| evaluate(text, symbols, Width::Address).map(|value| value as u16);
+
+    use self::Operand::*;
+
+    let instruction = match (mnemonic, ops.as_slice()) {
+        ("CLS", &[]) => Instruction::Cls,
+        ("RET", &[]) => Instruction::Ret,
+        ("SYS", &[Value(addr)]) => Instruction::Sys { addr: address(addr)? },
+        ("JP", &[Value(addr)]) => Instruction::Jmp { addr: address(addr)? },
+        ("JP", &[Register(0), Value(addr)]) => Instruction::JmpV0 { addr: address(addr)? },
+        ("CALL", &[Value(addr)]) => Instruction::Call { addr: address(addr)? },
+
+        ("SE", &[Register(x), Register(y)]) => Instruction::SeVxVy { x, y },
+        ("SE", &[Register(x), Value(v)]) => Instruction::SeVxByte { x, byte: byte(v)? },
+        ("SNE", &[Register(x), Register(y)]) => Instruction::SneVxVy { x, y },
+        ("SNE", &[Register(x), Value(v)]) => Instruction::SneVxByte { x, byte: byte(v)? },
+
+        ("LD", &[Register(x), Register(y)]) => Instruction::LdVxVy { x, y },
+        ("LD", &[Register(x), Value(v)]) => Instruction::LdVxByte { x, byte: byte(v)? },
+        ("LD", &[I, Value(addr)]) => Instruction::LdI { addr: address(addr)? },
+        ("LD", &[Register(x), Dt]) => Instruction::LdVxDt { x },
+        ("LD", &[Register(x), K]) => Instruction::LdVxK { x },
+        ("LD", &[Dt, Register(x)]) => Instruction::LdDtVx { x },
+        ("LD", &[St, Register(x)]) => Instruction::LdStVx { x },
+        ("LD", &[F, Register(x)]) => Instruction::LdFVx { x },
+        ("LD", &[B, Register(x)]) => Instruction::LdBVx { x },
+        ("LD", &[IndirectI, Register(x)]) => Instruction::LdIVx { x },
+        ("LD", &[Register(x), IndirectI]) => Instruction::LdVxI { x },
+
+        ("ADD", &[Register(x), Register(y)]) => Instruction::AddVxVy { x, y },
+        ("ADD", &[Register(x), Value(v)]) => Instruction::AddVxByte { x, byte: byte(v)? },
+        ("ADD", &[I, Register(x)]) => Instruction::AddIVx { x },
+
+        ("OR", &[Register(x), Register(y)]) => Instruction::OrVxVy { x, y },
+        ("AND", &[Register(x), Register(y)]) => Instruction::AndVxVy { x, y },
+        ("XOR", &[Register(x), Register(y)]) => Instruction::XorVxVy { x, y },
+        ("SUB", &[Register(x), Register(y)]) => Instruction::SubVxVy { x, y },
+        ("SUBN", &[Register(x), Register(y)]) => Instruction::SubnVxVy { x, y },
+        ("SHR", &[Register(x)]) => Instruction::ShrVxVy { x, y: x },
+        ("SHR", &[Register(x), Register(y)]) => Instruction::ShrVxVy { x, y },
+        ("SHL", &[Register(x)]) => Instruction::ShlVxVy { x, y: x },
+        ("SHL", &[Register(x), Register(y)]) => Instruction::ShlVxVy { x, y },
+
+        ("RND", &[Register(x), Value(v)]) => Instruction::Rnd { x, byte: byte(v)? },
+        ("DRW", &[Register(x), Register(y), Value(n)]) => {
+            Instruction::Drw { x, y, n: evaluate(n, symbols, Width::Nibble)? as u8 }
+        }
+        ("SKP", &[Register(x)]) => Instruction::Skp { x },
+        ("SKNP", &[Register(x)]) => Instruction::Sknp { x },
+
+        _ if is_mnemonic(mnemonic) => {
+            return Err(format!("invalid operands for {}: {}", mnemonic, operands.join(", ")))
+        }
+        _ => return Err(format!("unknown instruction {}", mnemonic)),
+    };
+
+    Ok(instruction)
+}
+
+fn is_mnemonic(mnemonic: &str) -> bool {
+    [
+        "CLS", "RET", "SYS", "JP", "CALL", "SE", "SNE", "LD", "ADD", "OR", "AND", "XOR", "SUB",
+        "SUBN", "SHR", "SHL", "RND", "DRW", "SKP", "SKNP",
+    ].contains(&mnemonic)
+}
+
+fn operand(text: &str) -> Operand<'_> {
+    let upper = text.to_uppercase();
+
+    match upper.as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::Dt,
+        "ST" => Operand::St,
+        "K" => Operand::K,
+        "F" => Operand::F,
+        "B" => Operand::B,
+        _ => match register(&upper) {
+            Some(x) => Operand::Register(x),
+            None => Operand::Value(text),
+        },
+    }
+}
+
+fn register(text: &str) -> Option<u8> {
+    if text.len() == 2 && text.starts_with('V') {
+        u8::from_str_radix(&text[1..], 16).ok()
+    } else {
+        None
+    }
+}
+
+/// Evaluates an operand and fits it into `width`, negative values are
+/// stored as two's complement, e.g. `ADD V0, -1` or `DW -2`.
+fn evaluate(text: &str, symbols: &HashMap<String, i64>, width: Width) -> Result<u32, String> {
+    let value = sum(text, symbols)?;
+
+    let (min, max) = match width {
+        Width::Nibble => (0, 0xF),
+        Width::Byte => (-0x80, 0xFF),
+        Width::Word => (-0x8000, 0xFFFF),
+        Width::Address => (0, 0xFFF),
+        Width::Any => (0, u32::MAX as i64),
+    };
+    if value < min || value > max {
+        return Err(match width {
+            Width::Nibble => format!("sprite height {} is larger than 15", text),
+            Width::Byte => format!("{} does not fit into a byte", text),
+            Width::Word => format!("{} does not fit into a word", text),
+            Width::Address => format!("address {} is out of range", text),
+            Width::Any => format!("{} is out of range", text),
+        });
+    }
+
+    //wrap negatives around to the width, max + 1 is its power of two
+    Ok(if value < 0 { value + max + 1 } else { value } as u32)
+}
+
+/// Adds and subtracts numbers and symbols like `sprites + 5 - 0x10`.
+fn sum(text: &str, symbols: &HashMap<String, i64>) -> Result<i64, String> {
+    let mut total: i64 = 0;
+    let mut sign = 1;
+    let mut expect_term = true;
+    let overflow = || format!("{} is out of range", text);
+
+    for token in tokenize_expression(text) {
+        match token {
+            "+" | "-" if expect_term => {
+                if token == "-" {
+                    sign = -sign;
+                }
+            }
+            "+" | "-" => {
+                sign = if token == "-" { -1 } else { 1 };
+                expect_term = true;
+            }
+            _ if expect_term => {
+                let value = match number(token)? {
+                    Some(value) => value,
+                    None => match symbols.get(token) {
+                        Some(&value) => value,
+                        None => return Err(format!("undefined symbol {}", token)),
+                    },
+                };
+                let term = value.checked_mul(sign).ok_or_else(overflow)?;
+                total = total.checked_add(term).ok_or_else(overflow)?;
+                sign = 1;
+                expect_term = false;
+            }
+            _ => return Err(format!("invalid expression {}", text)),
+        }
+    }
+
+    if expect_term {
+        return Err(format!("invalid expression {}", text));
+    }
+
+    Ok(total)
+}
+
+fn tokenize_expression(text: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+
+    for (i, c) in text.char_indices() {
+        if c == '+' || c == '-' || c.is_whitespace() {
+            if let Some(s) = start.take() {
+                tokens.push(&text[s..i]);
+            }
+            if !c.is_whitespace() {
+                tokens.push(&text[i..i + 1]);
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(s) = start {
+        tokens.push(&text[s..]);
+    }
+
+    tokens
+}
+
+/// Parses a number literal, None if `text` is no number but a name.
+fn number(text: &str) -> Result<Option<i64>, String> {
+    let lower = text.to_lowercase();
+
+    let (digits, radix) = if let Some(hex) = lower.strip_prefix("0x").or_else(|| lower.strip_prefix('$')) {
+        (hex, 16)
+    } else if let Some(binary) = lower.strip_prefix("0b").or_else(|| lower.strip_prefix('%')) {
+        (binary, 2)
+    } else if lower.starts_with(|c: char| c.is_ascii_digit()) {
+        (&lower[..], 10)
+    } else {
+        return Ok(None);
+    };
+
+    match i64::from_str_radix(digits, radix) {
+        Ok(value) => Ok(Some(value)),
+        Err(_) => Err(format!("invalid number {}", text)),
+    }
+}
+
+fn define(symbols: &mut HashMap<String, i64>, name: &str, value: i64) -> Result<(), String> {
+    let valid = name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if !valid {
+        return Err(format!("invalid name {}", name));
+    }
+
+    let upper = name.to_uppercase();
+    if register(&upper).is_some() || ["I", "DT", "ST", "K", "F", "B"].contains(&upper.as_str()) {
+        return Err(format!("{} is a reserved name", name));
+    }
+
+    if symbols.insert(name.to_string(), value).is_some() {
+        return Err(format!("{} is defined twice", name));
+    }
+
+    Ok(())
+}
+
+/// Splits `NAME EQU value` and `NAME = value` into name and value.
+fn constant(statement: &str) -> Option<(&str, &str)> {
+    if let Some(equals) = statement.find('=') {
+        let name = statement[..equals].trim();
+        if !name.is_empty() && !name.contains(char::is_whitespace) {
+            return Some((name, statement[equals + 1..].trim()));
+        }
+    }
+
+    let (name, rest) = split_statement(statement);
+    let (keyword, value) = split_statement(rest);
+    if keyword.eq_ignore_ascii_case("EQU") {
+        return Some((name, value));
+    }
+
+    None
+}
+
+fn single(operands: &[String]) -> Result<&str, String> {
+    match operands {
+        [operand] => Ok(operand),
+        _ => Err(format!("expected one operand, got {}", operands.len())),
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &line[..i],
+            _ => (),
+        }
+    }
+
+    line
+}
+
+/// Position of the colon ending a leading label, if there is one.
+fn label_end(line: &str) -> Option<usize> {
+    let colon = line.find(':')?;
+    let label = &line[..colon];
+
+    if !label.is_empty() && !label.contains(|c: char| c.is_whitespace() || c == '"') {
+        Some(colon)
+    } else {
+        None
+    }
+}
+
+fn split_statement(statement: &str) -> (&str, &str) {
+    match statement.find(char::is_whitespace) {
+        Some(i) => (&statement[..i], statement[i..].trim()),
+        None => (statement, ""),
+    }
+}
+
+fn split_operands(operands: &str) -> Vec<String> {
+    if operands.is_empty() {
+        return Vec::new();
+    }
+
+    let mut result = Vec::new();
+    let mut current = String::new();
+    let mut in_string = false;
+
+    for c in operands.chars() {
+        match c {
+            '"' => {
+                in_string = !in_string;
+                current.push(c);
+            }
+            ',' if !in_string => {
+                result.push(current.trim().to_string());
+                current.clear();
+            }
+            _ => current.push(c),
+        }
+    }
+    result.push(current.trim().to_string());
+
+    result
+}
+
+fn string_literal(operand: &str) -> Option<&str> {
+    if operand.len() >= 2 && operand.starts_with('"') && operand.ends_with('"') {
+        Some(&operand[1..operand.len() - 1])
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use disasm::{self, Item};
+    use instruction::decode;
+
+    fn error(source: &str) -> AsmError {
+        assemble(source).unwrap_err()
+    }
+
+    #[test]
+    fn assembles_every_instruction_form() {
+        let source = "
+            CLS
+            RET
+            JP 0x208
+            CALL 0x300
+            SE V1, 0x22
+            SNE V1, V2
+            LD V3, [I]
+            LD [I], V3
+            LD B, V4
+            LD F, V5
+            LD V6, K
+            LD DT, V7
+            LD ST, V8
+            ADD I, V9
+            SHL VA, VB
+            RND VC, 0x0F
+            DRW VD, VE, 5
+            SKP VF
+            SKNP V0
+            JP V0, 0x400";
+        let rom = assemble(source).unwrap();
+        let opcodes: Vec<u16> = rom.chunks(2).map(|word| u16::from(word[0]) << 8 | u16::from(word[1])).collect();
+
+        assert_eq!(opcodes, [
+            0x00E0, 0x00EE, 0x1208, 0x2300, 0x3122, 0x9120, 0xF365, 0xF355, 0xF433, 0xF529,
+            0xF60A, 0xF715, 0xF818, 0xF91E, 0x8ABE, 0xCC0F, 0xDDE5, 0xEF9E, 0xE0A1, 0xB400,
+        ]);
+    }
+
+    #[test]
+    fn disassembly_assembles_back_to_the_same_rom() {
+        let rom = assemble("
+            start:  LD I, ball
+                    LD V0, 10
+            loop:   DRW V0, V1, 2
+                    ADD V0, -1
+                    SE V0, 0
+                    JP loop
+                    JP start
+            ball:   DB 0x80, 0x40").unwrap();
+
+        let listing = disasm::disassemble(&rom, 0x200).unwrap();
+        assert!(listing.lines.iter().any(|line| line.item == Item::Sprite(0x40)));
+
+        //the listing prints addresses and raw bytes in front of the code
+        let source: String = listing.to_string().lines().map(|line| {
+            let line = line.trim_start();
+            if line.starts_with("0x") {
+                format!("{}\n", &line[17..])
+            } else {
+                format!("{}\n", line)
+            }
+        }).collect();
+        assert_eq!(assemble(&source).unwrap(), rom);
+    }
+
+    #[test]
+    fn labels_may_be_used_before_they_are_defined() {
+        let rom = assemble("JP end\nCLS\nend: JP end").unwrap();
+        assert_eq!(decode(u16::from(rom[0]) << 8 | u16::from(rom[1])), Instruction::Jmp { addr: 0x204 });
+    }
+
+    #[test]
+    fn constants_expressions_and_org() {
+        let rom = assemble("BASE EQU 0x10\nSIZE = BASE + 2 - 1\nLD V0, SIZE\nORG 0x206\nDB %101, $FF").unwrap();
+        assert_eq!(rom, [0x60, 0x11, 0x00, 0x00, 0x00, 0x00, 0x05, 0xFF]);
+    }
+
+    #[test]
+    fn negative_values_wrap_to_the_operand_width() {
+        assert_eq!(assemble("ADD V0, -1").unwrap(), [0x70, 0xFF]);
+        assert_eq!(assemble("DB -128").unwrap(), [0x80]);
+        assert_eq!(assemble("DW -1, -2").unwrap(), [0xFF, 0xFF, 0xFF, 0xFE]);
+        assert_eq!(assemble("MINUS = -1\nDW MINUS").unwrap(), [0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn errors_carry_the_line_number() {
+        assert_eq!(error("CLS\n\nJP nowhere"), AsmError { line: 3, message: "undefined symbol nowhere".to_string() });
+        assert_eq!(error("CLS\nFOO V0").message, "unknown instruction FOO");
+        assert_eq!(error("LD V0, 0x100").message, "0x100 does not fit into a byte");
+        assert_eq!(error("DB -129").message, "-129 does not fit into a byte");
+        assert_eq!(error("DW 0x10000").line, 1);
+        assert_eq!(error("JP 0x1000").message, "address 0x1000 is out of range");
+        assert_eq!(error("DRW V0, V1, 16").message, "sprite height 16 is larger than 15");
+        assert_eq!(error("a: CLS\na: CLS").message, "a is defined twice");
+        assert_eq!(error("ORG 0x300\nORG 0x200").line, 2);
+    }
+
+    #[test]
+    fn huge_numbers_are_errors_instead_of_overflows() {
+        assert_eq!(error("DW 99999999999999999999").message, "invalid number 99999999999999999999");
+        assert_eq!(error("DW 0x7FFFFFFFFFFFFFFF + 1").message, "0x7FFFFFFFFFFFFFFF + 1 is out of range");
+        assert_eq!(error("X = 0x7FFFFFFFFFFFFFFF\nDW X + X").line, 2);
+    }
+}
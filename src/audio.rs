@@ -0,0 +1,196 @@
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+/// Receives the buzzer state of a running `Chip8`.
+///
+/// `start_tone` is called when the sound timer is set to a non-zero value
+/// and `stop_tone` when it is cleared or has counted down to zero.
+pub trait AudioSink {
+    fn start_tone(&mut self);
+
+    fn stop_tone(&mut self);
+}
+
+/// Lets a frontend keep a handle on a sink that is owned by the emulator,
+/// e.g. to pull samples from a `SquareWave` in its audio callback.
+impl<S: AudioSink + ?Sized> AudioSink for Arc<Mutex<S>> {
+    fn start_tone(&mut self) {
+        if let Ok(mut sink) = self.lock() {
+            sink.start_tone();
+        }
+    }
+
+    fn stop_tone(&mut self) {
+        if let Ok(mut sink) = self.lock() {
+            sink.stop_tone();
+        }
+    }
+}
+
+/// Square wave synthesizer producing signed 16 bit mono PCM samples.
+#[derive(Debug, Clone)]
+pub struct SquareWave {
+    sample_rate: u32,
+    frequency: f32,
+    amplitude: i16,
+    phase: f32,
+    playing: bool,
+}
+
+impl SquareWave {
+    pub const DEFAULT_FREQUENCY: f32 = 440.0;
+
+    pub const DEFAULT_AMPLITUDE: i16 = 4000;
+
+    pub fn new(sample_rate: u32) -> SquareWave {
+        SquareWave::with_tone(sample_rate, SquareWave::DEFAULT_FREQUENCY, SquareWave::DEFAULT_AMPLITUDE)
+    }
+
+    pub fn with_tone(sample_rate: u32, frequency: f32, amplitude: i16) -> SquareWave {
+        SquareWave {
+            sample_rate,
+            frequency,
+            amplitude,
+            phase: 0.0,
+            playing: false,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Fills `out` with the next samples, silence while the tone is off.
+    pub fn render(&mut self, out: &mut [i16]) {
+        let step = self.frequency / self.sample_rate as f32;
+
+        for sample in out.iter_mut() {
+            if !self.playing {
+                *sample = 0;
+                continue;
+            }
+
+            *sample = if self.phase < 0.5 { self.amplitude } else { -self.amplitude };
+
+            self.phase += step;
+            if self.phase >= 1.0 {
+                self.phase -= 1.0;
+            }
+        }
+    }
+}
+
+impl AudioSink for SquareWave {
+    fn start_tone(&mut self) {
+        if !self.playing {
+            self.phase = 0.0;
+            self.playing = true;
+        }
+    }
+
+    fn stop_tone(&mut self) {
+        self.playing = false;
+    }
+}
+
+/// Writes 16 bit mono samples as a WAV file, e.g. the buzzer of a headless
+/// run rendered with `SquareWave`.
+pub fn write_wav<W: Write>(samples: &[i16], sample_rate: u32, mut out: W) -> io::Result<()> {
+    let data = (samples.len() * 2) as u32;
+
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data).to_le_bytes())?;
+    out.write_all(b"WAVEfmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    //PCM, one channel, two bytes per frame
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&sample_rate.to_le_bytes())?;
+    out.write_all(&(sample_rate * 2).to_le_bytes())?;
+    out.write_all(&2u16.to_le_bytes())?;
+    out.write_all(&16u16.to_le_bytes())?;
+
+    out.write_all(b"data")?;
+    out.write_all(&data.to_le_bytes())?;
+    for sample in samples {
+        out.write_all(&sample.to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //8 samples per period, 4 high then 4 low
+    fn wave() -> SquareWave {
+        SquareWave::with_tone(8000, 1000.0, 100)
+    }
+
+    #[test]
+    fn renders_the_period_of_the_tone() {
+        let mut wave = wave();
+        wave.start_tone();
+
+        let mut samples = [0; 16];
+        wave.render(&mut samples);
+        assert_eq!(samples, [100, 100, 100, 100, -100, -100, -100, -100, 100, 100, 100, 100, -100, -100, -100, -100]);
+    }
+
+    #[test]
+    fn keeps_the_phase_across_buffers() {
+        let mut whole = wave();
+        whole.start_tone();
+        let mut expected = [0; 20];
+        whole.render(&mut expected);
+
+        let mut split = wave();
+        split.start_tone();
+        let mut samples = [0; 20];
+        let (first, second) = samples.split_at_mut(7);
+        split.render(first);
+        //a start while playing does not restart the period
+        split.start_tone();
+        split.render(second);
+
+        assert_eq!(samples, expected);
+    }
+
+    #[test]
+    fn is_silent_when_stopped_and_restarts_the_period() {
+        let mut wave = wave();
+        let mut samples = [1; 4];
+        wave.render(&mut samples);
+        assert_eq!(samples, [0; 4]);
+
+        wave.start_tone();
+        wave.render(&mut [0; 6]);
+        wave.stop_tone();
+        wave.render(&mut samples);
+        assert_eq!((samples, wave.is_playing()), ([0; 4], false));
+
+        wave.start_tone();
+        let mut samples = [0; 5];
+        wave.render(&mut samples);
+        assert_eq!(samples, [100, 100, 100, 100, -100]);
+    }
+
+    #[test]
+    fn writes_a_wav_header() {
+        let mut out = Vec::new();
+        write_wav(&[1, -2], 8000, &mut out).unwrap();
+
+        assert_eq!(out.len(), 48);
+        assert_eq!(&out[..4], b"RIFF");
+        assert_eq!(&out[4..8], &40u32.to_le_bytes());
+        assert_eq!(&out[8..16], b"WAVEfmt ");
+        assert_eq!(&out[24..28], &8000u32.to_le_bytes());
+        assert_eq!(&out[36..44], b"data\x04\0\0\0");
+        assert_eq!(&out[44..], &[1, 0, 0xFE, 0xFF]);
+    }
+}
@@ -1,40 +1,117 @@
-use rand;
-
-use std::io;
 use std::io::prelude::*;
+use std::fmt;
 use std::fs::File;
-use std::collections::HashMap;
-use std::thread::sleep;
-use std::time::Duration;
+
+use audio::AudioSink;
+use clock::Clock;
+use config::Config;
+use quirks::{LoadStoreIndex, Quirks};
+use movie::KeyEvent;
+use random::{RandomSource, XorShift};
+use error::{Chip8Error, LoadError};
+use instruction::{decode, Instruction};
+use trace::{Snapshot, TraceEntry, TraceSink};
+
+pub const MEMORY_SIZE: usize = 4096;
+
+pub const PROGRAM_START: usize = 0x200;
+
+pub const MAX_ROM_SIZE: usize = MEMORY_SIZE - PROGRAM_START;
+
+pub const SCREEN_WIDTH: usize = 64;
+
+pub const SCREEN_HEIGHT: usize = 32;
+
+/// What happened while running one 60 Hz frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FrameOutcome {
+    /// Number of instructions executed.
+    pub cycles: u32,
+    pub screen_changed: bool,
+    /// The frame ended early because the program is blocked on Fx0A.
+    pub waiting_for_key: bool,
+    /// The frame ended early because the program jumped to itself.
+    pub halted: bool,
+}
+
+/// Progress of an Fx0A instruction blocked on the keypad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum KeyWait {
+    Idle,
+    Press,
+    Release(u8),
+    Done(u8),
+}
+
+/// What happened while executing a single instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StepOutcome {
+    /// Address the instruction was fetched from.
+    pub address: u16,
+    pub opcode: u16,
+    /// The instruction cleared the screen or toggled at least one pixel.
+    pub screen_changed: bool,
+    /// The program is blocked on Fx0A until a key event arrives.
+    pub waiting_for_key: bool,
+    /// The sound timer went from zero to non-zero.
+    pub sound_started: bool,
+    /// The sound timer was set back to zero while the tone was playing.
+    pub sound_stopped: bool,
+    /// The instruction jumped to itself, which is how most programs halt.
+    pub self_jump: bool,
+}
 
 pub struct Chip8 {
 
     
-    opcode: u16,
+    pub(crate) opcode: u16,
     
-    memory: [u8; 4096],
+    pub(crate) memory: [u8; MEMORY_SIZE],
+
+    pub(crate) registers: [u8; 16],
+
+    pub(crate) indexing: u16,
+
+    pub(crate) program_counter: usize,
+
+    pub(crate) gfx: [u8; 64*32],
+
+    pub(crate) delay_timer: u8,
 
-    registers: [u8; 16],
+    pub(crate) sound_timer: u8,
 
-    indexing: u16,
+    pub(crate) stack: [u16; 16],
 
-    program_counter: usize,
+    pub(crate) stack_pointer: usize,
 
-    gfx: [u8; 64*32],
+    /// Bit n is set while key n is held.
+    pub(crate) keys: u16,
 
-    delay_timer: u8,
+    pub(crate) key_wait: KeyWait,
 
-    sound_timer: u8,
+    pub(crate) quirks: Quirks,
 
-    stack: [u16; 16],
+    pub(crate) step: StepOutcome,
 
-    stack_pointer: usize,
+    pub(crate) audio: Option<Box<dyn AudioSink + Send>>,
 
-    keys: [u8; 16],
+    pub(crate) clock: Clock,
 
-    jump_table: HashMap<u16, fn(&mut Chip8) >,
+    pub(crate) rng: Box<dyn RandomSource + Send>,
 
-    wait_index: usize,
+    pub(crate) seed: u64,
+
+    pub(crate) frame: u64,
+
+    /// Instructions left in the current frame, 0 between frames.
+    pub(crate) frame_budget: u32,
+
+    pub(crate) input_log: Option<Vec<KeyEvent>>,
+
+    /// Instructions executed since power on.
+    pub(crate) cycles: u64,
+
+    pub(crate) trace: Option<Box<dyn TraceSink + Send>>,
 }
 
 
@@ -42,31 +119,62 @@ impl Chip8{
 
     
     
-    pub fn new(path: &str) -> Chip8{
+    pub fn new(path: &str) -> Result<Chip8, LoadError>{
+        let file = File::open(path)?;
+
+        Chip8::from_reader(file)
+    }
+
+    pub fn from_reader<R: Read>(reader: R) -> Result<Chip8, LoadError>{
+        let mut rom = Vec::new();
+
+        //read one byte more than fits, so oversized roms are detected
+        reader.take(MAX_ROM_SIZE as u64 + 1).read_to_end(&mut rom)?;
+
+        Chip8::from_rom(&rom)
+    }
+
+    pub fn from_rom(rom: &[u8]) -> Result<Chip8, LoadError>{
+        Chip8::with_config(rom, Config::default())
+    }
+
+    pub fn with_quirks(rom: &[u8], quirks: Quirks) -> Result<Chip8, LoadError>{
+        Chip8::with_config(rom, Config{ quirks, ..Config::default() })
+    }
+
+    pub fn with_config(rom: &[u8], config: Config) -> Result<Chip8, LoadError>{
        let mut chip8 = Chip8{
             opcode:0,
-            memory: [0;4096],
+            memory: [0;MEMORY_SIZE],
             registers: [0;16],
             indexing:0,
-            program_counter:0x200,
+            program_counter:PROGRAM_START,
             gfx: [0;64*32],
             delay_timer: 0,
             sound_timer: 0,
             stack: [0;16],
             stack_pointer: 0,
-            keys: [0;16],
-            jump_table: HashMap::new(),
-            wait_index: 255,
+            keys: 0,
+            key_wait: KeyWait::Idle,
+            quirks: config.quirks,
+            step: StepOutcome::default(),
+            audio: None,
+            clock: Clock::new(config.instructions_per_second),
+            rng: Box::new(XorShift::new(config.seed)),
+            seed: config.seed,
+            frame: 0,
+            frame_budget: 0,
+            input_log: None,
+            cycles: 0,
+            trace: None,
         };
 
 
-        chip8.load_game(path);
+        chip8.load_rom(rom)?;
        
         chip8.load_fontset();
 
-        chip8.init_jumptable();
-
-        chip8
+        Ok(chip8)
     }
 
    
@@ -89,346 +197,460 @@ impl Chip8{
                            0xF0, 0x80, 0xF0, 0x80, 0x80  // F
                            );
 
-        let end = (0x50 + fontset.len()) as usize;
-        for i in 0x50..end {
-            self.memory[i] = fontset[i-0x50];
-        }
+        let end = 0x50 + fontset.len();
+        self.memory[0x50..end].copy_from_slice(&fontset);
 
     }
 
-    pub fn load_game(&mut self, path: &str){
-        let file = File::open(path).unwrap();
-        
+    pub fn load_game(&mut self, path: &str) -> Result<(), LoadError>{
+        let mut file = File::open(path)?;
 
-        let mut i= 512;
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
 
-        let content: Vec<Result<u8,io::Error>> = file.bytes().collect();
+        self.load_rom(&content)
+    }
 
-        println!("filesize: {}", content.len());
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), LoadError>{
+        if rom.is_empty() {
+            return Err(LoadError::EmptyRom);
+        }
 
-        for b  in  content{
-            self.memory[i] = b.unwrap();
-            i = i + 1;
+        if rom.len() > MAX_ROM_SIZE {
+            return Err(LoadError::RomTooLarge{ size: rom.len(), max: MAX_ROM_SIZE });
         }
-    }
 
-    fn init_jumptable(&mut self){
+        let end = PROGRAM_START + rom.len();
+        self.memory[PROGRAM_START..end].copy_from_slice(rom);
 
-        self.jump_table.insert(0x00E0, Self::op_cls );
-        self.jump_table.insert(0x00EE, Self::op_ret );
-        self.jump_table.insert(0x1000, Self::op_jmp );
-        self.jump_table.insert(0x2000, Self::op_call );
-        self.jump_table.insert(0x3000, Self::op_se_vx_byte);
-        self.jump_table.insert(0x4000, Self::op_sne_vx_byte);
-        self.jump_table.insert(0x5000, Self::op_se_vx_vy);
-        self.jump_table.insert(0x6000, Self::op_ld_vx_byte);
-        self.jump_table.insert(0x7000, Self::op_add_vx_byte);
+        Ok(())
+    }
 
-        self.jump_table.insert(0x8000, Self::op_ld_vx_vy);
-        self.jump_table.insert(0x8001, Self::op_or_vx_vy);
-        self.jump_table.insert(0x8002, Self::op_and_vx_vy);
-        self.jump_table.insert(0x8003, Self::op_xor_vx_vy);
-        self.jump_table.insert(0x8004, Self::op_add_vx_vy);
-        self.jump_table.insert(0x8005, Self::op_sub_vx_vy);
-        self.jump_table.insert(0x8006, Self::op_shr_vx_vy);
-        self.jump_table.insert(0x8007, Self::op_subn_vx_vy);
-        self.jump_table.insert(0x800E, Self::op_shl_vx_vy);
+    pub fn emulate_cycle(&mut self) -> Result<StepOutcome, Chip8Error>{
 
-        self.jump_table.insert(0x9000, Self::op_sne_vx_vy);
-        self.jump_table.insert(0xA000, Self::op_ld_i);
-        self.jump_table.insert(0xB000, Self::op_jmp_v0);
-        self.jump_table.insert(0xC000, Self::op_rnd);
-        self.jump_table.insert(0xD000, Self::op_drw);
-        
-        self.jump_table.insert(0xE09E, Self::op_skp);
-        self.jump_table.insert(0xE0A1, Self::op_sknp);
-        
-        self.jump_table.insert(0xF007, Self::op_ld_vx_dt);
-        self.jump_table.insert(0xF00A, Self::op_ld_vx_k);
-        self.jump_table.insert(0xF015, Self::op_ld_dt_vx);
-        self.jump_table.insert(0xF018, Self::op_ld_st_vx);
-        self.jump_table.insert(0xF01E, Self::op_add_i_vx);
-        self.jump_table.insert(0xF029, Self::op_ld_f_vx);
-        self.jump_table.insert(0xF033, Self::op_ld_b_vx);
-        self.jump_table.insert(0xF055, Self::op_ld_i_vx);
-        self.jump_table.insert(0xF065, Self::op_ld_vx_i);
-    }
 
-    pub fn emulate_cycle(&mut self){
+        self.fetch()?;
 
+        let address = self.program_counter;
+        self.program_counter += 2;
 
-        self.fetch();
+        self.step = StepOutcome{
+            address: address as u16,
+            opcode: self.opcode,
+            ..StepOutcome::default()
+        };
 
-      //  self.log_state();
-        
-        self.decode_and_execute();
+        //leave the pc on the faulting instruction, so hosts can report it
+        let instruction = decode(self.opcode);
+        let before = self.trace.as_ref().map(|_| Snapshot::of(self));
+        if let Err(err) = self.execute(instruction) {
+            self.program_counter = address;
+            return Err(err);
+        }
 
+        if let Some(before) = before {
+            let entry = TraceEntry{
+                cycle: self.cycles,
+                frame: self.frame,
+                address: address as u16,
+                opcode: self.opcode,
+                before,
+                after: Snapshot::of(self),
+            };
+            if let Some(ref mut trace) = self.trace {
+                trace.record(&entry);
+            }
+        }
+        self.cycles += 1;
 
+        Ok(self.step)
        }
 
-    /*
-    fn debug_halt(&self){
-         let mut input = String::new();
-        io::stdin().read_line(&mut input);
+    /// Executes one frame worth of instructions and ticks the timers once.
+    ///
+    /// Call it 60 times a second, the number of instructions per frame is
+    /// taken from the configured speed.
+    pub fn run_frame(&mut self) -> Result<FrameOutcome, Chip8Error>{
+        let mut frame = FrameOutcome::default();
+
+        //finish a frame that was started with step_instruction
+        if self.frame_budget == 0 {
+            self.frame_budget = self.clock.next_frame();
+        }
+
+        while self.frame_budget > 0 {
+            let step = self.emulate_cycle()?;
+            self.frame_budget -= 1;
+
+            frame.cycles += 1;
+            frame.screen_changed |= step.screen_changed;
 
+            //nothing happens until a key event or ever again, skip the rest
+            if step.waiting_for_key {
+                frame.waiting_for_key = true;
+                break;
+            }
+            if step.self_jump {
+                frame.halted = true;
+                break;
+            }
+            if self.waits_for_display(&step) {
+                break;
+            }
+        }
+
+        self.end_frame();
+
+        Ok(frame)
     }
 
-     fn log_state(&self){
-        println!("--------AFTER FETCH -------\n");
-        println!("opcode:{:x}  \npc at:{} I at:{} sp at:{} dt at:{}",
-                                                           self.opcode,
-                                                           self.program_counter,
-                                                           self.indexing,
-                                                           self.stack_pointer,
-                                                           self.delay_timer);
-        println!("registers:");
-        for i in 0..self.registers.len(){
-            print!( "v{:X}={}  ",i, self.registers[i]);    
+    /// Executes a single instruction, e.g. for a debugger. Frames end after
+    /// the same instructions as with `run_frame`, so the timers keep counting
+    /// down at the configured speed.
+    pub fn step_instruction(&mut self) -> Result<StepOutcome, Chip8Error>{
+        if self.frame_budget == 0 {
+            self.frame_budget = self.clock.next_frame().max(1);
         }
-        print!("\n");
 
-        println!("\nstack:");
-        for i in 0..self.stack.len(){
-            print!( "s{:X}={}  ",i, self.stack[i]);
+        let step = self.emulate_cycle()?;
+        self.frame_budget -= 1;
+
+        if self.frame_budget == 0 || step.waiting_for_key || step.self_jump || self.waits_for_display(&step) {
+            self.end_frame();
         }
-        println!("\n--------------------");
+
+        Ok(step)
     }
-    */
 
-    pub fn decrease_dt(&mut self){
-        if self.delay_timer == 0 {
-           return
+    fn waits_for_display(&self, step: &StepOutcome) -> bool {
+        match decode(step.opcode) {
+            Instruction::Drw{ .. } => self.quirks.display_wait,
+            _ => false,
         }
-        self.delay_timer = self.delay_timer  - 1;
     }
 
-    fn fetch(&mut self){
-        let upper = (self.memory[self.program_counter] as u16) << 8;
-        let lower = self.memory[self.program_counter+1] as u16;
+    fn end_frame(&mut self){
+        self.frame_budget = 0;
+        self.tick_timers();
+        self.frame += 1;
+    }
 
-        self.opcode = upper | lower;
+    /// Number of frames run so far.
+    pub fn frame_count(&self) -> u64 {
+        self.frame
+    }
 
+    /// Instructions executed since power on.
+    pub fn cycle_count(&self) -> u64 {
+        self.cycles
+    }
 
+    pub fn instructions_per_second(&self) -> u32 {
+        self.clock.instructions_per_second()
     }
 
-    fn decode_and_execute(&mut self){
-        let upper = self.opcode & 0xF000;
+    pub fn set_instructions_per_second(&mut self, instructions_per_second: u32){
+        self.clock.set_instructions_per_second(instructions_per_second);
+    }
 
-        if ( upper >= 0x1000 && upper < 0x8000 ) 
-            ||  (upper == 0x9000)
-            ||  (upper >= 0xA000 && upper < 0xE000) {
-            self.execute_decoded(upper); 
-        } 
-        else if upper >= 0x8000 && upper <0x9000 {
-            let key = self.opcode & 0xF00F;
-            self.execute_decoded(key);
+    /// Counts the delay and sound timers down by one, call it at 60 Hz.
+    /// `run_frame` already does this.
+    pub fn tick_timers(&mut self){
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
         }
-        else if upper >= 0xE000 {
-            let key = self.opcode & 0xF0FF;
-            self.execute_decoded(key);
+
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
+
+            if self.sound_timer == 0 {
+                if let Some(ref mut audio) = self.audio {
+                    audio.stop_tone();
+                }
+            }
         }
-        else {
-            let key = self.opcode;
-            self.execute_decoded(key);
+    }
+
+    pub fn set_audio_sink(&mut self, sink: Box<dyn AudioSink + Send>){
+        self.audio = Some(sink);
+    }
+
+    /// Reports every executed instruction to `sink`, None stops tracing.
+    pub fn set_trace_sink(&mut self, sink: Option<Box<dyn TraceSink + Send>>){
+        self.trace = sink;
+    }
+
+    /// Replaces the seeded default source of CXNN's random numbers.
+    pub fn set_random_source(&mut self, source: Box<dyn RandomSource + Send>){
+        self.rng = source;
+    }
+
+    /// The seed the machine was created with, or the one of the save state
+    /// it was restored from.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn config(&self) -> Config {
+        Config{
+            quirks: self.quirks,
+            instructions_per_second: self.clock.instructions_per_second(),
+            seed: self.seed,
         }
+    }
 
+    pub fn is_sound_playing(&self) -> bool {
+        self.sound_timer > 0
     }
 
-    fn execute_decoded(&mut self, key: u16){
-            let func = self.jump_table.get(&key).map(|x| *x);
-            
-            match func{
-                Some(func) => func(self),
-                None => {
-                        println!("invalid op: {}", self.opcode);
-                        sleep(Duration::from_millis(1000000));
-                        return;
-                }
-            }();
+    fn fetch(&mut self) -> Result<(), Chip8Error>{
+        if self.program_counter + 1 >= MEMORY_SIZE {
+            return Err(Chip8Error::MemoryOutOfBounds{
+                address: self.program_counter as u16,
+                access: self.program_counter + 1,
+            });
+        }
 
+        let upper = (self.memory[self.program_counter] as u16) << 8;
+        let lower = self.memory[self.program_counter+1] as u16;
 
-            self.program_counter = self.program_counter + 2;
+        self.opcode = upper | lower;
+
+        Ok(())
+    }
+
+    fn execute(&mut self, instruction: Instruction) -> Result<(), Chip8Error>{
+        match instruction {
+            Instruction::Cls => self.op_cls(),
+            Instruction::Ret => self.op_ret(),
+            Instruction::Jmp{ addr } => self.op_jmp(addr),
+            Instruction::Call{ addr } => self.op_call(addr),
+            Instruction::SeVxByte{ x, byte } => self.op_se_vx_byte(x as usize, byte),
+            Instruction::SneVxByte{ x, byte } => self.op_sne_vx_byte(x as usize, byte),
+            Instruction::SeVxVy{ x, y } => self.op_se_vx_vy(x as usize, y as usize),
+            Instruction::LdVxByte{ x, byte } => self.op_ld_vx_byte(x as usize, byte),
+            Instruction::AddVxByte{ x, byte } => self.op_add_vx_byte(x as usize, byte),
+
+            Instruction::LdVxVy{ x, y } => self.op_ld_vx_vy(x as usize, y as usize),
+            Instruction::OrVxVy{ x, y } => self.op_or_vx_vy(x as usize, y as usize),
+            Instruction::AndVxVy{ x, y } => self.op_and_vx_vy(x as usize, y as usize),
+            Instruction::XorVxVy{ x, y } => self.op_xor_vx_vy(x as usize, y as usize),
+            Instruction::AddVxVy{ x, y } => self.op_add_vx_vy(x as usize, y as usize),
+            Instruction::SubVxVy{ x, y } => self.op_sub_vx_vy(x as usize, y as usize),
+            Instruction::ShrVxVy{ x, y } => self.op_shr_vx_vy(x as usize, y as usize),
+            Instruction::SubnVxVy{ x, y } => self.op_subn_vx_vy(x as usize, y as usize),
+            Instruction::ShlVxVy{ x, y } => self.op_shl_vx_vy(x as usize, y as usize),
+
+            Instruction::SneVxVy{ x, y } => self.op_sne_vx_vy(x as usize, y as usize),
+            Instruction::LdI{ addr } => self.op_ld_i(addr),
+            Instruction::JmpV0{ addr } => self.op_jmp_v0(addr),
+            Instruction::Rnd{ x, byte } => self.op_rnd(x as usize, byte),
+            Instruction::Drw{ x, y, n } => self.op_drw(x as usize, y as usize, n),
+
+            Instruction::Skp{ x } => self.op_skp(x as usize),
+            Instruction::Sknp{ x } => self.op_sknp(x as usize),
+
+            Instruction::LdVxDt{ x } => self.op_ld_vx_dt(x as usize),
+            Instruction::LdVxK{ x } => self.op_ld_vx_k(x as usize),
+            Instruction::LdDtVx{ x } => self.op_ld_dt_vx(x as usize),
+            Instruction::LdStVx{ x } => self.op_ld_st_vx(x as usize),
+            Instruction::AddIVx{ x } => self.op_add_i_vx(x as usize),
+            Instruction::LdFVx{ x } => self.op_ld_f_vx(x as usize),
+            Instruction::LdBVx{ x } => self.op_ld_b_vx(x as usize),
+            Instruction::LdIVx{ x } => self.op_ld_i_vx(x as usize),
+            Instruction::LdVxI{ x } => self.op_ld_vx_i(x as usize),
+
+            //0NNN called into the host cpu, which we cannot emulate
+            Instruction::Sys{ .. } | Instruction::Unknown{ .. } => Err(Chip8Error::UnknownOpcode{
+                opcode: self.opcode,
+                address: self.instruction_address(),
+            }),
+        }
     }
     
-    fn op_cls(&mut self){        
-        self.gfx = [0; 64*32]
+    fn op_cls(&mut self) -> Result<(), Chip8Error>{        
+        self.gfx = [0; 64*32];
+        self.step.screen_changed = true;
+
+        Ok(())
     }
 
-    fn op_ret(&mut self){        
+    fn op_ret(&mut self) -> Result<(), Chip8Error>{        
+        if self.stack_pointer == 0 {
+            return Err(Chip8Error::StackUnderflow{ address: self.instruction_address() });
+        }
+
+        self.stack_pointer -= 1;
         self.program_counter = (self.stack[self.stack_pointer]) as usize;
-        self.stack_pointer = self.stack_pointer -1;
 
+        Ok(())
     }
     
-    fn op_jmp(&mut self){
-        self.program_counter = ((self.opcode & 0x0FFF) -2) as usize;
+    fn op_jmp(&mut self, addr: u16) -> Result<(), Chip8Error>{
+        self.step.self_jump = addr == self.instruction_address();
+        self.program_counter = addr as usize;
+
+        Ok(())
     }
     
-    fn op_call(&mut self){
-        self.stack_pointer = self.stack_pointer + 1;
+    fn op_call(&mut self, addr: u16) -> Result<(), Chip8Error>{
+        if self.stack_pointer == self.stack.len() {
+            return Err(Chip8Error::StackOverflow{ address: self.instruction_address() });
+        }
+
         self.stack[self.stack_pointer] = self.program_counter as u16;
-        self.program_counter = ((self.opcode & 0x0FFF) - 2) as usize;
+        self.stack_pointer += 1;
+        self.program_counter = addr as usize;
+
+        Ok(())
     }
    
-    fn op_se_vx_byte(&mut self){
-        let index = self.get_vx();
-        let cmp = self.get_byte();
-
-        if self.registers[index] == cmp{
-            self.program_counter = self.program_counter + 2;
+    fn op_se_vx_byte(&mut self, x: usize, byte: u8) -> Result<(), Chip8Error>{
+        if self.registers[x] == byte {
+            self.program_counter += 2;
         }
+
+        Ok(())
     }
     
-    fn op_sne_vx_byte(&mut self){
-        let index = self.get_vx();       
-        let cmp = self.get_byte();
-
-        if self.registers[index] != cmp{
-            self.program_counter = self.program_counter + 2;
+    fn op_sne_vx_byte(&mut self, x: usize, byte: u8) -> Result<(), Chip8Error>{
+        if self.registers[x] != byte {
+            self.program_counter += 2;
         }
+
+        Ok(())
     }
    
-    fn op_se_vx_vy(&mut self){
-        let index_vx = self.get_vx();
-        let index_vy = self.get_vy();
-
-        if self.registers[index_vx] == self.registers[index_vy]{
-            self.program_counter = self.program_counter + 2;
+    fn op_se_vx_vy(&mut self, x: usize, y: usize) -> Result<(), Chip8Error>{
+        if self.registers[x] == self.registers[y] {
+            self.program_counter += 2;
         }
 
+        Ok(())
     }
     
-    fn op_ld_vx_byte(&mut self){
-        let c = (self.opcode & 0x00FF) as u8;
-        let index = self.get_vx();
+    fn op_ld_vx_byte(&mut self, x: usize, byte: u8) -> Result<(), Chip8Error>{
+        self.registers[x] = byte;
 
-        self.registers[index] = c;
+        Ok(())
     }
   
-    fn op_add_vx_byte(&mut self){
-        let byte = self.get_byte();
-        let index = self.get_vx();
-
-        let (sum, _) = self.add_with_carry(self.registers[index],byte);
-        self.registers[index] = sum;
+    fn op_add_vx_byte(&mut self, x: usize, byte: u8) -> Result<(), Chip8Error>{
+        //7XNN never touches the carry flag
+        self.registers[x] = self.registers[x].wrapping_add(byte);
 
+        Ok(())
     }
          
-    fn op_ld_vx_vy(&mut self){
-        let index_vy = self.get_vy();
-        let index_vx = self.get_vx();
+    fn op_ld_vx_vy(&mut self, x: usize, y: usize) -> Result<(), Chip8Error>{
+        self.registers[x] = self.registers[y];
 
-        self.registers[index_vx] = self.registers[index_vy];
+        Ok(())
     }
 
-    fn op_or_vx_vy(&mut self){
-        let index_vy = self.get_vy();
-        let index_vx = self.get_vx();
+    fn op_or_vx_vy(&mut self, x: usize, y: usize) -> Result<(), Chip8Error>{
+        self.registers[x] |= self.registers[y];
+        self.reset_vf_after_logic();
 
-        self.registers[index_vx] = self.registers[index_vx] | self.registers[index_vy];
-   }
+        Ok(())
+    }
 
-    fn op_and_vx_vy(&mut self){
-        let index_vy = self.get_vy();
-        let index_vx = self.get_vx();
+    fn op_and_vx_vy(&mut self, x: usize, y: usize) -> Result<(), Chip8Error>{
+        self.registers[x] &= self.registers[y];
+        self.reset_vf_after_logic();
 
-        self.registers[index_vx] = self.registers[index_vx] & self.registers[index_vy]; 
+        Ok(())
     }
 
-    fn op_xor_vx_vy(&mut self){
-        let index_vy = self.get_vy();
-        let index_vx = self.get_vx();
+    fn op_xor_vx_vy(&mut self, x: usize, y: usize) -> Result<(), Chip8Error>{
+        self.registers[x] ^= self.registers[y];
+        self.reset_vf_after_logic();
 
-        self.registers[index_vx] = self.registers[index_vx] ^ self.registers[index_vy]; 
-   }
+        Ok(())
+    }
 
-    fn op_add_vx_vy(&mut self){
-        let index_vx = self.get_vx();
-        let index_vy = self.get_vy();
+    fn op_add_vx_vy(&mut self, x: usize, y: usize) -> Result<(), Chip8Error>{
+        let (sum, carry) = self.registers[x].overflowing_add(self.registers[y]);
 
-        let result: u16 = self.registers[index_vx] as u16 + self.registers[index_vy] as u16;
-        let carry = if result >  0  {0} else { 1 };
+        self.registers[x] = sum;
+        self.registers[0xF] = carry as u8;
 
-        self.registers[index_vx] = (result & 0x00FF) as u8;
-        self.registers[15] = carry;
-        
+        Ok(())
     }
 
-    fn op_sub_vx_vy(&mut self){
-        let index_vx = self.get_vx();
-        let index_vy = self.get_vy();
+    fn op_sub_vx_vy(&mut self, x: usize, y: usize) -> Result<(), Chip8Error>{
+        let (difference, borrow) = self.registers[x].overflowing_sub(self.registers[y]);
 
-        let result: i16 = self.registers[index_vx] as i16 - self.registers[index_vy] as i16;
-        let carry = if self.registers[index_vx] > self.registers[index_vy] { 1 } else {0};
+        self.registers[x] = difference;
+        self.registers[0xF] = !borrow as u8;
+
+        Ok(())
+    }
 
-        self.registers[index_vx] = (result & 0x00FF) as u8;
-        self.registers[15] = carry;
+    fn op_shr_vx_vy(&mut self, x: usize, y: usize) -> Result<(), Chip8Error>{
+        let value = self.shift_source(x, y);
 
+        self.registers[x] = value >> 1;
+        self.registers[0xF] = value & 0x01;
 
+        Ok(())
     }
 
-    fn op_shr_vx_vy(&mut self){
-        let index_vx = self.get_vx();
+    fn op_subn_vx_vy(&mut self, x: usize, y: usize) -> Result<(), Chip8Error>{
+        let (difference, borrow) = self.registers[y].overflowing_sub(self.registers[x]);
 
-        let carry = self.registers[index_vx] & 0x01;
+        self.registers[x] = difference;
+        self.registers[0xF] = !borrow as u8;
 
-        self.registers[index_vx] = self.registers[index_vx] >> 1;
-        self.registers[15] = carry;
+        Ok(())
     }
 
-    fn op_subn_vx_vy(&mut self){
-        let index_vx = self.get_vx();
-        let index_vy = self.get_vy();
+    fn op_shl_vx_vy(&mut self, x: usize, y: usize) -> Result<(), Chip8Error>{
+        let value = self.shift_source(x, y);
 
-        let result: i16 = self.registers[index_vy] as i16 - self.registers[index_vx] as i16;
-        let carry = if self.registers[index_vy] > self.registers[index_vx] { 1 } else {0};
+        self.registers[x] = value << 1;
+        self.registers[0xF] = value >> 7;
 
-        self.registers[index_vx] = (result & 0x00FF) as u8;
-        self.registers[15] = carry;
+        Ok(())
+    }
 
-   }
+    fn op_sne_vx_vy(&mut self, x: usize, y: usize) -> Result<(), Chip8Error>{
+        if self.registers[x] != self.registers[y] {
+            self.program_counter += 2;
+        }
 
-    fn op_shl_vx_vy(&mut self){
-        let index_vx = self.get_vx();
+        Ok(())
+    }
 
-        let carry = self.registers[index_vx] & 0x80;
+    fn op_ld_i(&mut self, addr: u16) -> Result<(), Chip8Error>{
+        self.indexing = addr;
 
-        self.registers[index_vx] = self.registers[index_vx] << 1;
-        self.registers[15] = carry;  
+        Ok(())
     }
 
-    fn op_sne_vx_vy(&mut self){
-        let index_vx = self.get_vx();
-        let index_vy = self.get_vy();
+    fn op_jmp_v0(&mut self, addr: u16) -> Result<(), Chip8Error>{
+        let register = if self.quirks.jump_uses_vx { (addr >> 8) as usize } else { 0 };
 
-        if self.registers[index_vx] != self.registers[index_vy] {
-            self.program_counter = self.program_counter + 2;
-        }
+        self.program_counter = (addr + self.registers[register] as u16) as usize;
 
-   }
-
-    fn op_ld_i(&mut self){
-        self.indexing = self.opcode & 0x0FFF;
+        Ok(())
     }
 
-    fn op_jmp_v0(&mut self){
-        let offset = (self.opcode & 0x0FFF) as u16;
-        self.program_counter = (offset + self.registers[0] as u16) as usize;
+    fn op_rnd(&mut self, x: usize, byte: u8) -> Result<(), Chip8Error>{
+        self.registers[x] = self.rng.next_byte() & byte;
 
+        Ok(())
     }
 
-    fn op_rnd(&mut self){
-        let index_vx = self.get_vx();
-        self.registers[index_vx] = rand::random::<u8>() & (self.opcode & 0x00FF) as u8 ;
-    }
+    fn op_drw(&mut self, x: usize, y: usize, n: u8) -> Result<(), Chip8Error>{
+        let height = n as u16;
+        self.check_memory(self.indexing as usize, height as usize)?;
 
-    fn op_drw(&mut self){
-        self.registers[0xF] = 0;
+        let initial_col_coord = self.registers[x];
+        let initial_row_coord = self.registers[y];
 
-        let initial_col_coord = self.registers[self.get_vx() ];
-        let initial_row_coord = self.registers[self.get_vy() ];
+        self.registers[0xF] = 0;
 
-        let height:u16 = self.opcode & 0x000F;
         let mut row: u16 = (initial_row_coord % 32) as u16;
 
 
@@ -437,169 +659,679 @@ impl Chip8{
             let sprite_row = self.memory[i as usize];
             
             
-            let mut col: u16 = initial_col_coord as u16;
-
             for j in 0..8{
             
+                let col: u16 = (initial_col_coord % 64) as u16 + j;
+
+                if self.quirks.clip_sprites && col >= 64 {
+                    break;
+                }
+
                 let pos = (row*64+(col % 64 )) as usize;
                 
                 let sprite_set = sprite_row & (0x80 >> j); 
 
 
                 if sprite_set != 0 {
+                    self.step.screen_changed = true;
                     if self.gfx[pos] != 0 {
                         self.registers[0xF] = 1;
                     }
                     self.gfx[pos] ^= 1;
                 }
-
-                col = col + 1 as u16;
             }
 
-            row = (row + 1) % 32;
+            row += 1;
+
+            if row == 32 {
+                if self.quirks.clip_sprites {
+                    break;
+                }
+                row = 0;
+            }
             
         }
 
-        
+        Ok(())
     }
    
-    fn op_skp(&mut self){
-        let key_index = self.registers[self.get_vx()] as usize;
+    fn op_skp(&mut self, x: usize) -> Result<(), Chip8Error>{
+        let key_index = (self.registers[x] & 0x0F) as usize;
 
-        if self.keys[key_index] == 1 {
+        if self.is_key_down(key_index as u8) {
             self.program_counter += 2;
         }
+
+        Ok(())
     }
     
-    fn op_sknp(&mut self){
-        let key_index = self.registers[self.get_vx()] as usize;
+    fn op_sknp(&mut self, x: usize) -> Result<(), Chip8Error>{
+        let key_index = (self.registers[x] & 0x0F) as usize;
 
-        if self.keys[key_index] == 0 {
+        if !self.is_key_down(key_index as u8) {
             self.program_counter += 2;
         }
+
+        Ok(())
     }
     
-    fn op_ld_vx_dt(&mut self){
-        let index = self.get_vx();
-        self.registers[index] = self.delay_timer;
+    fn op_ld_vx_dt(&mut self, x: usize) -> Result<(), Chip8Error>{
+        self.registers[x] = self.delay_timer;
+
+        Ok(())
     }
     
-    fn op_ld_vx_k(&mut self){
-        let index = self.get_vx();
-        self.wait_index = index as usize;
+    fn op_ld_vx_k(&mut self, x: usize) -> Result<(), Chip8Error>{
+        if let KeyWait::Done(key) = self.key_wait {
+            self.registers[x] = key;
+            self.key_wait = KeyWait::Idle;
+            return Ok(());
+        }
+
+        if self.key_wait == KeyWait::Idle {
+            self.key_wait = KeyWait::Press;
+        }
+
+        //stay on this instruction until a key event completes the wait
+        self.program_counter = self.instruction_address() as usize;
+        self.step.waiting_for_key = true;
+
+        Ok(())
     }
     
-    fn op_ld_dt_vx(&mut self){
-        let vx = self.registers[self.get_vx()];
-        self.delay_timer = vx;
+    fn op_ld_dt_vx(&mut self, x: usize) -> Result<(), Chip8Error>{
+        self.delay_timer = self.registers[x];
+
+        Ok(())
     }
 
-    fn op_ld_st_vx(&mut self){
-        let vx = self.registers[self.get_vx()];
-        self.sound_timer = vx;  
+    fn op_ld_st_vx(&mut self, x: usize) -> Result<(), Chip8Error>{
+        let vx = self.registers[x];
+
+        self.step.sound_started = self.sound_timer == 0 && vx != 0;
+        self.step.sound_stopped = self.sound_timer != 0 && vx == 0;
+        self.set_sound_timer(vx);
+
+        Ok(())
     }
 
-    fn op_add_i_vx(&mut self){
-        let vx = self.registers[self.get_vx()];
-        self.indexing = self.indexing + vx as u16;
+    fn op_add_i_vx(&mut self, x: usize) -> Result<(), Chip8Error>{
+        self.indexing = self.indexing.wrapping_add(self.registers[x] as u16);
 
+        Ok(())
     }
 
-    fn op_ld_f_vx(&mut self){
-        let sprite_index = self.registers[self.get_vx()] as u16;
+    fn op_ld_f_vx(&mut self, x: usize) -> Result<(), Chip8Error>{
+        let sprite_index = (self.registers[x] & 0x0F) as u16;
         
-        self.indexing = ((0x50 +  (sprite_index * 5)) &  0xFFFF ) as u16;
+        self.indexing = 0x50 + (sprite_index * 5);
+
+        Ok(())
     }
 
-    fn op_ld_b_vx(&mut self){
-        let val = self.registers[self.get_vx()];
+    fn op_ld_b_vx(&mut self, x: usize) -> Result<(), Chip8Error>{
+        let val = self.registers[x];
         let i = self.indexing as usize;
+        self.check_memory(i, 3)?;
 
         self.memory[i] = val / 100;
         self.memory[i+1] = (val / 10 ) % 10;
         self.memory[i+2] = val % 10;
 
-
+        Ok(())
     }
     
-    fn op_ld_i_vx(&mut self){
-        let index_vx = self.get_vx();
+    fn op_ld_i_vx(&mut self, x: usize) -> Result<(), Chip8Error>{
         let mem_addr = self.indexing as usize;
+        self.check_memory(mem_addr, x + 1)?;
 
-        
-        for i in 0..index_vx+1{
-            self.memory[mem_addr+i]  = self.registers[i as usize];
-        }
+        self.memory[mem_addr..=mem_addr + x].copy_from_slice(&self.registers[..=x]);
+
+        self.advance_index(x);
+
+        Ok(())
     }
     
-    fn op_ld_vx_i(&mut self){
-        let index_vx = self.get_vx();
+    fn op_ld_vx_i(&mut self, x: usize) -> Result<(), Chip8Error>{
         let mem_addr = self.indexing as usize;
+        self.check_memory(mem_addr, x + 1)?;
 
-        for i in 0..index_vx+1{
-            self.registers[i]  = self.memory[i + mem_addr];
-        }
+        self.registers[..=x].copy_from_slice(&self.memory[mem_addr..=mem_addr + x]);
+
+        self.advance_index(x);
+
+        Ok(())
     }
  
-    fn add_with_carry(&self, a:u8, b:u8) -> (u8,u8){
-        let s = (a as u16 + b as u16) & 0xFF ;
-        let c = if s & 0xFF00 != 0 {1u8} else {0u8};
-        
-        (s as u8, c)
+    /// Moves I past FX55 and FX65 as far as the quirks say.
+    fn advance_index(&mut self, x: usize){
+        match self.quirks.load_store_index {
+            LoadStoreIndex::Unchanged => (),
+            LoadStoreIndex::PlusX => self.indexing += x as u16,
+            LoadStoreIndex::PlusXPlusOne => self.indexing += x as u16 + 1,
+        }
+    }
 
+    fn shift_source(&self, x: usize, y: usize) -> u8 {
+        if self.quirks.shift_uses_vy {
+            self.registers[y]
+        } else {
+            self.registers[x]
+        }
     }
 
-    fn get_byte(&self) -> u8 {
-        (self.opcode & 0x00FF) as u8
+    fn reset_vf_after_logic(&mut self) {
+        if self.quirks.logic_resets_vf {
+            self.registers[0xF] = 0;
+        }
     }
 
-    fn get_vy(&self) -> usize {
-        ((self.opcode & 0x00F0) >> 4) as usize
+    fn check_memory(&self, start: usize, len: usize) -> Result<(), Chip8Error>{
+        if start + len > MEMORY_SIZE {
+            return Err(Chip8Error::MemoryOutOfBounds{
+                address: self.instruction_address(),
+                access: start + len - 1,
+            });
+        }
+
+        Ok(())
     }
 
-    fn get_vx(&self) -> usize {
-        ((self.opcode & 0x0F00) >> 8) as usize
+    fn instruction_address(&self) -> u16 {
+        (self.program_counter - 2) as u16
     }
-   
+
     pub fn get_gfx(&self) -> &[u8; 64*32]{
         &self.gfx
     }
+
+    /// V0 to VF.
+    pub fn registers(&self) -> &[u8; 16]{
+        &self.registers
+    }
+
+    /// The register Vx, `x` is taken modulo 16.
+    pub fn register(&self, x: u8) -> u8 {
+        self.registers[x as usize & 0xF]
+    }
+
+    pub fn set_register(&mut self, x: u8, value: u8){
+        self.registers[x as usize & 0xF] = value;
+    }
+
+    /// The index register I.
+    pub fn index(&self) -> u16 {
+        self.indexing
+    }
+
+    pub fn set_index(&mut self, value: u16){
+        self.indexing = value;
+    }
+
+    /// The address of the next instruction.
+    pub fn program_counter(&self) -> u16 {
+        self.program_counter as u16
+    }
+
+    /// Returns false, changing nothing, if `address` is outside of memory.
+    pub fn set_program_counter(&mut self, address: u16) -> bool {
+        if address as usize >= MEMORY_SIZE {
+            return false;
+        }
+        self.program_counter = address as usize;
+        true
+    }
+
+    /// The return addresses, the innermost call last.
+    pub fn stack(&self) -> &[u16]{
+        &self.stack[..self.stack_pointer.min(self.stack.len())]
+    }
+
+    /// Replaces the return addresses, the innermost call last. Returns
+    /// false, changing nothing, for more than 16 of them.
+    pub fn set_stack(&mut self, addresses: &[u16]) -> bool {
+        if addresses.len() > self.stack.len() {
+            return false;
+        }
+        self.stack[..addresses.len()].copy_from_slice(addresses);
+        self.stack_pointer = addresses.len();
+        true
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8){
+        self.delay_timer = value;
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    /// Starts or stops the tone of the audio sink like FX18 does.
+    pub fn set_sound_timer(&mut self, value: u8){
+        let started = self.sound_timer == 0 && value != 0;
+        let stopped = self.sound_timer != 0 && value == 0;
+        self.sound_timer = value;
+
+        if let Some(ref mut audio) = self.audio {
+            if started {
+                audio.start_tone();
+            } else if stopped {
+                audio.stop_tone();
+            }
+        }
+    }
+
+    /// All 4 KB, the font at 0x50 and the program from 0x200 on.
+    pub fn memory(&self) -> &[u8; MEMORY_SIZE]{
+        &self.memory
+    }
+
+    /// Up to `length` bytes from `address`, fewer at the end of memory.
+    pub fn read_memory(&self, address: u16, length: usize) -> &[u8]{
+        let start = (address as usize).min(MEMORY_SIZE);
+        let end = start.saturating_add(length).min(MEMORY_SIZE);
+        &self.memory[start..end]
+    }
+
+    /// Returns false, writing nothing, if the bytes do not fit into memory.
+    pub fn write_memory(&mut self, address: u16, bytes: &[u8]) -> bool {
+        let start = address as usize;
+        if start + bytes.len() > MEMORY_SIZE {
+            return false;
+        }
+        self.memory[start..start + bytes.len()].copy_from_slice(bytes);
+        true
+    }
    
-    pub fn update_keys(&mut self, input: char) {
-        for i in 0.. self.keys.len(){
-            self.keys[i] = 0;
-        }
-
-        let keystroke = match input{
-            '1' => 1,
-            '2' => 2,
-            '3' => 3,
-            '4' => 0xC,
-            'q' => 4,
-            'w' => 5,
-            'e' => 6,
-            'r' => 0xD,
-            'a' => 7,
-            's' => 8,
-            'd' => 9,
-            'f' => 0xE,
-            'y' => 0xA,
-            'x' => 0,
-            'c' => 0xB,
-            'v' => 0xF,
-             _ => 255, 
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn is_waiting_for_key(&self) -> bool {
+        matches!(self.key_wait, KeyWait::Press | KeyWait::Release(_))
+    }
+
+    /// Presses one of the 16 keys, 0x0 to 0xF. Other held keys stay down.
+    pub fn key_down(&mut self, key: u8) {
+        self.set_key(key, true);
+    }
+
+    /// Releases one of the 16 keys, 0x0 to 0xF.
+    pub fn key_up(&mut self, key: u8) {
+        self.set_key(key, false);
+    }
+
+    pub fn is_key_down(&self, key: u8) -> bool {
+        self.keys & 1 << (key & 0xF) != 0
+    }
+
+    /// The held keys, bit n for key n.
+    pub fn keys(&self) -> u16 {
+        self.keys
+    }
+
+    /// Holds exactly the keys set in `keys`, bit n for key n. Goes through
+    /// `set_key`, so a waiting FX0A sees the changes.
+    pub fn set_keys(&mut self, keys: u16) {
+        for key in 0..16 {
+            if self.is_key_down(key) != (keys & 1 << key != 0) {
+                self.set_key(key, keys & 1 << key != 0);
+            }
+        }
+    }
+
+    /// Presses or releases one of the 16 keys, 0x0 to 0xF.
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        let key = key & 0xF;
+        let before = (self.keys, self.key_wait);
+
+        if pressed {
+            self.keys |= 1 << key;
+            if self.key_wait == KeyWait::Press {
+                self.key_wait = if self.quirks.key_wait_release {
+                    KeyWait::Release(key)
+                } else {
+                    KeyWait::Done(key)
+                };
+            }
+        } else {
+            self.keys &= !(1 << key);
+            if self.key_wait == KeyWait::Release(key) {
+                self.key_wait = KeyWait::Done(key);
+            }
+        }
+
+        //only events that changed something matter for a replay
+        if let Some(ref mut log) = self.input_log {
+            if before != (self.keys, self.key_wait) {
+                log.push(KeyEvent{ frame: self.frame, key, pressed });
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Chip8 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Chip8")
+            .field("program_counter", &self.program_counter)
+            .field("index", &self.indexing)
+            .field("registers", &self.registers)
+            .field("stack", &self.stack())
+            .field("delay_timer", &self.delay_timer)
+            .field("sound_timer", &self.sound_timer)
+            .field("keys", &self.keys)
+            .field("key_wait", &self.key_wait)
+            .field("quirks", &self.quirks)
+            .field("clock", &self.clock)
+            .field("seed", &self.seed)
+            .field("frame", &self.frame)
+            .field("cycles", &self.cycles)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The registers, stack, timers and keys, as the debugger prints them.
+impl fmt::Display for Chip8 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "PC 0x{:03X}  I 0x{:03X}  SP {}  DT {:02X}  ST {:02X}  frame {}",
+                 self.program_counter,
+                 self.indexing,
+                 self.stack_pointer,
+                 self.delay_timer,
+                 self.sound_timer,
+                 self.frame)?;
+
+        for (row, registers) in self.registers.chunks(8).enumerate() {
+            let registers: Vec<String> = registers.iter().enumerate()
+                .map(|(x, value)| format!("V{:X} {:02X}", row * 8 + x, value))
+                .collect();
+            writeln!(f, "{}", registers.join("  "))?;
+        }
+
+        let stack: Vec<String> = self.stack().iter().map(|address| format!("0x{:03X}", address)).collect();
+        writeln!(f, "stack {}", if stack.is_empty() { "empty".to_string() } else { stack.join(" ") })?;
+
+        let keys: Vec<String> = (0..16).filter(|&key| self.is_key_down(key)).map(|key| format!("{:X}", key)).collect();
+        let waiting = if self.is_waiting_for_key() { ", waiting for a key" } else { "" };
+        write!(f, "keys {}{}", if keys.is_empty() { "none".to_string() } else { keys.join(" ") }, waiting)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Loads `rom`, sets V0 to VF from `registers` and runs `count`
+    /// instructions.
+    fn run(rom: &[u8], registers: &[u8], quirks: Quirks, count: usize) -> Chip8 {
+        let mut chip8 = Chip8::with_quirks(rom, quirks).unwrap();
+        chip8.registers[..registers.len()].copy_from_slice(registers);
+        for _ in 0..count {
+            chip8.emulate_cycle().unwrap();
+        }
+        chip8
+    }
+
+    #[test]
+    fn add_sets_vf_to_the_carry() {
+        let chip8 = run(&[0x80, 0x14], &[0xFF, 0x02], Quirks::default(), 1);
+        assert_eq!((chip8.registers[0], chip8.registers[0xF]), (0x01, 1));
+
+        let chip8 = run(&[0x80, 0x14], &[0xFD, 0x02], Quirks::default(), 1);
+        assert_eq!((chip8.registers[0], chip8.registers[0xF]), (0xFF, 0));
+    }
+
+    #[test]
+    fn sub_sets_vf_when_there_is_no_borrow() {
+        let chip8 = run(&[0x80, 0x15], &[0x05, 0x05], Quirks::default(), 1);
+        assert_eq!((chip8.registers[0], chip8.registers[0xF]), (0x00, 1));
+
+        let chip8 = run(&[0x80, 0x15], &[0x03, 0x05], Quirks::default(), 1);
+        assert_eq!((chip8.registers[0], chip8.registers[0xF]), (0xFE, 0));
+    }
+
+    #[test]
+    fn subn_sets_vf_when_there_is_no_borrow() {
+        let chip8 = run(&[0x80, 0x17], &[0x05, 0x05], Quirks::default(), 1);
+        assert_eq!((chip8.registers[0], chip8.registers[0xF]), (0x00, 1));
+
+        let chip8 = run(&[0x80, 0x17], &[0x05, 0x03], Quirks::default(), 1);
+        assert_eq!((chip8.registers[0], chip8.registers[0xF]), (0xFE, 0));
+    }
+
+    #[test]
+    fn flag_wins_over_the_result_in_vf() {
+        let mut registers = [0; 16];
+        registers[0x1] = 0x01;
+        registers[0xF] = 0xFF;
+        let chip8 = run(&[0x8F, 0x14], &registers, Quirks::default(), 1);
+        assert_eq!(chip8.registers[0xF], 1);
+    }
+
+    #[test]
+    fn add_byte_leaves_vf_alone() {
+        let mut registers = [0; 16];
+        registers[0x0] = 0x02;
+        registers[0xF] = 0x07;
+        let chip8 = run(&[0x70, 0xFF], &registers, Quirks::default(), 1);
+        assert_eq!((chip8.registers[0], chip8.registers[0xF]), (0x01, 0x07));
+    }
+
+    #[test]
+    fn shifts_set_vf_to_the_bit_shifted_out() {
+        let chip8 = run(&[0x80, 0x16], &[0x81, 0x00], Quirks::default(), 1);
+        assert_eq!((chip8.registers[0], chip8.registers[0xF]), (0x40, 1));
+
+        let chip8 = run(&[0x80, 0x1E], &[0x81, 0x00], Quirks::default(), 1);
+        assert_eq!((chip8.registers[0], chip8.registers[0xF]), (0x02, 1));
+    }
+
+    #[test]
+    fn shift_uses_vy_quirk() {
+        let quirks = Quirks { shift_uses_vy: true, ..Quirks::default() };
+        let chip8 = run(&[0x80, 0x1E], &[0x00, 0x41], quirks, 1);
+        assert_eq!((chip8.registers[0], chip8.registers[0xF]), (0x82, 0));
+    }
+
+    #[test]
+    fn logic_resets_vf_quirk() {
+        let mut registers = [0; 16];
+        registers[0xF] = 0x07;
+        let chip8 = run(&[0x80, 0x11], &registers, Quirks::default(), 1);
+        assert_eq!(chip8.registers[0xF], 0x07);
+
+        let quirks = Quirks { logic_resets_vf: true, ..Quirks::default() };
+        let chip8 = run(&[0x80, 0x11], &registers, quirks, 1);
+        assert_eq!(chip8.registers[0xF], 0);
+    }
+
+    #[test]
+    fn load_store_index_quirk() {
+        //LD I, 0x300 then LD [I], V2
+        let rom = [0xA3, 0x00, 0xF2, 0x55];
+        let index = |load_store_index| {
+            let quirks = Quirks { load_store_index, ..Quirks::default() };
+            run(&rom, &[1, 2, 3], quirks, 2).indexing
         };
 
-        if keystroke == 255 {
-            return;
+        assert_eq!(index(LoadStoreIndex::Unchanged), 0x300);
+        assert_eq!(index(LoadStoreIndex::PlusX), 0x302);
+        assert_eq!(index(LoadStoreIndex::PlusXPlusOne), 0x303);
+        assert_eq!(Quirks::chip48().load_store_index, LoadStoreIndex::PlusX);
+        assert_ne!(Quirks::chip48(), Quirks::super_chip());
+    }
+
+    #[test]
+    fn jump_uses_vx_quirk() {
+        let chip8 = run(&[0xB2, 0x10], &[0x01, 0x00, 0x04], Quirks::default(), 1);
+        assert_eq!(chip8.program_counter, 0x211);
+
+        let quirks = Quirks { jump_uses_vx: true, ..Quirks::default() };
+        let chip8 = run(&[0xB2, 0x10], &[0x01, 0x00, 0x04], quirks, 1);
+        assert_eq!(chip8.program_counter, 0x214);
+    }
+
+    #[test]
+    fn skips_with_a_nonzero_low_nibble_are_unknown() {
+        for &opcode in &[[0x50, 0x11], [0x90, 0x1F]] {
+            let mut chip8 = Chip8::from_rom(&opcode).unwrap();
+            let opcode = u16::from(opcode[0]) << 8 | u16::from(opcode[1]);
+            assert_eq!(chip8.emulate_cycle(), Err(Chip8Error::UnknownOpcode { opcode, address: 0x200 }));
+            assert_eq!(chip8.program_counter, 0x200);
         }
+    }
+
+    #[test]
+    fn draw_reads_the_coordinates_before_clearing_vf() {
+        //LD I, 0x50 (the font's 0) then DRW VF, V1, 5
+        let mut registers = [0; 16];
+        registers[0xF] = 8;
+        let chip8 = run(&[0xA0, 0x50, 0xDF, 0x15], &registers, Quirks::default(), 2);
+        assert_eq!(&chip8.gfx[..12], &[0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn font_address_uses_the_low_nibble() {
+        let chip8 = run(&[0xF0, 0x29], &[0x1A], Quirks::default(), 1);
+        assert_eq!(chip8.indexing, 0x50 + 0xA * 5);
+    }
 
-        self.keys[keystroke] = 1;
+    #[test]
+    fn add_to_i_wraps() {
+        let mut chip8 = Chip8::from_rom(&[0xF0, 0x1E]).unwrap();
+        chip8.set_index(0xFFFF);
+        chip8.set_register(0, 2);
+        chip8.emulate_cycle().unwrap();
+        assert_eq!(chip8.index(), 0x0001);
+    }
+
+    #[test]
+    fn key_wait_finishes_on_a_press() {
+        let quirks = Quirks { key_wait_release: false, ..Quirks::default() };
+        let mut chip8 = Chip8::with_quirks(&[0xF3, 0x0A, 0x12, 0x02], quirks).unwrap();
+
+        for _ in 0..2 {
+            assert!(chip8.emulate_cycle().unwrap().waiting_for_key);
+            assert_eq!((chip8.program_counter(), chip8.is_waiting_for_key()), (0x200, true));
+        }
+
+        chip8.key_down(0xB);
+        assert!(!chip8.emulate_cycle().unwrap().waiting_for_key);
+        assert_eq!((chip8.register(3), chip8.program_counter()), (0xB, 0x202));
+        assert!(!chip8.is_waiting_for_key());
+    }
+
+    #[test]
+    fn key_wait_release_quirk_waits_for_the_same_key_to_go_up() {
+        let quirks = Quirks { key_wait_release: true, ..Quirks::default() };
+        let mut chip8 = Chip8::with_quirks(&[0xF3, 0x0A, 0x12, 0x02], quirks).unwrap();
+        chip8.emulate_cycle().unwrap();
+
+        chip8.key_down(0xB);
+        assert!(chip8.emulate_cycle().unwrap().waiting_for_key);
+        chip8.key_down(0xC);
+        chip8.key_up(0xC);
+        assert!(chip8.emulate_cycle().unwrap().waiting_for_key);
+
+        chip8.key_up(0xB);
+        assert!(!chip8.emulate_cycle().unwrap().waiting_for_key);
+        assert_eq!((chip8.register(3), chip8.program_counter()), (0xB, 0x202));
+    }
+
+    #[test]
+    fn keys_already_down_do_not_finish_a_key_wait() {
+        let mut chip8 = Chip8::with_quirks(&[0xF3, 0x0A], Quirks { key_wait_release: false, ..Quirks::default() }).unwrap();
+        chip8.key_down(0x4);
+        assert!(chip8.emulate_cycle().unwrap().waiting_for_key);
+        assert!(chip8.emulate_cycle().unwrap().waiting_for_key);
+    }
+
+    #[test]
+    fn key_mask_edges() {
+        let mut chip8 = Chip8::from_rom(&[0xE0, 0x9E]).unwrap();
+
+        chip8.key_down(0xF);
+        chip8.key_down(0xF);
+        assert_eq!(chip8.keys(), 0x8000);
+        chip8.key_down(0x0);
+        assert_eq!(chip8.keys(), 0x8001);
+        chip8.key_up(0xF);
+        chip8.key_up(0xF);
+        assert_eq!(chip8.keys(), 0x0001);
+
+        //only the low nibble picks the key, like EX9E does with VX
+        chip8.key_down(0x1F);
+        assert!(chip8.is_key_down(0xF));
+        chip8.set_register(0, 0xFF);
+        chip8.emulate_cycle().unwrap();
+        assert_eq!(chip8.program_counter(), 0x204);
+
+        chip8.set_keys(0x0000);
+        assert_eq!(chip8.keys(), 0);
+        chip8.set_keys(0xFFFF);
+        assert!((0..16).all(|key| chip8.is_key_down(key)));
+    }
 
-        if self.wait_index != 255 {
-            self.registers[self.wait_index] = keystroke as u8;
-            self.wait_index = 255;
+    #[test]
+    fn accessors_show_the_state_a_program_left_behind() {
+        let rom = [
+            0x60, 0x12, //LD V0, 0x12
+            0x61, 0x34, //LD V1, 0x34
+            0xA3, 0x00, //LD I, 0x300
+            0xF0, 0x15, //LD DT, V0
+            0xF1, 0x18, //LD ST, V1
+            0xF1, 0x33, //LD B, V1
+            0x22, 0x12, //CALL 0x212
+            0x00, 0x00,
+            0x00, 0x00,
+            0xF0, 0x29, //LD F, V0
+            0xD0, 0x15, //DRW V0, V1, 5
+            0x12, 0x16, //JP 0x216
+        ];
+        let mut chip8 = Chip8::from_rom(&rom).unwrap();
+        for _ in 0..9 {
+            chip8.step_instruction().unwrap();
         }
 
+        assert_eq!(chip8.register(0), 0x12);
+        assert_eq!(chip8.register(0x11), 0x34);
+        assert_eq!(&chip8.registers()[..3], &[0x12, 0x34, 0]);
+        assert_eq!(chip8.register(0xF), 0);
+        //the font digit 2
+        assert_eq!(chip8.index(), 0x50 + 2 * 5);
+        assert_eq!(chip8.program_counter(), 0x216);
+        assert_eq!(chip8.stack(), &[0x20E]);
+        assert_eq!((chip8.delay_timer(), chip8.sound_timer()), (0x12, 0x34));
+        assert!(chip8.is_sound_playing());
+
+        assert_eq!(chip8.read_memory(0x300, 3), &[0, 5, 2]);
+        assert_eq!(&chip8.memory()[0x200..0x202], &[0x60, 0x12]);
+        assert_eq!(chip8.read_memory(0xFFE, 8).len(), 2);
+
+        //the top row of the 2 is 0xF0, drawn at x 18, y 52 wrapped to 20
+        let row = &chip8.get_gfx()[20 * SCREEN_WIDTH..21 * SCREEN_WIDTH];
+        assert_eq!(&row[17..23], &[0, 1, 1, 1, 1, 0]);
+        assert_eq!(chip8.get_gfx().iter().filter(|&&pixel| pixel != 0).count(), 14);
+
+        assert_eq!((chip8.cycle_count(), chip8.frame_count()), (9, 0));
+        chip8.key_down(0x5);
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.keys(), 0x0020);
+        assert_eq!(chip8.frame_count(), 1);
+        assert_eq!((chip8.delay_timer(), chip8.sound_timer()), (0x11, 0x33));
+        assert_eq!(chip8.program_counter(), 0x216);
+
+        assert!(chip8.set_stack(&[]));
+        assert!(chip8.write_memory(0x300, &[7]));
+        assert!(!chip8.write_memory(0xFFF, &[1, 2]));
+        assert_eq!((chip8.stack(), chip8.read_memory(0x300, 1)), (&[][..], &[7][..]));
     }
 }
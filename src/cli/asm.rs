@@ -0,0 +1,37 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use chip8::asm;
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut source = None;
+    let mut output = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(args.next().ok_or_else(super::usage)?.clone()),
+            _ if source.is_none() => source = Some(arg),
+            _ => return Err(super::usage()),
+        }
+    }
+
+    let source = source.ok_or_else(super::usage)?;
+    let output = output.unwrap_or_else(|| {
+        Path::new(source).with_extension("ch8").to_string_lossy().into_owned()
+    });
+
+    let text = String::from_utf8(super::read_file(source)?)
+        .map_err(|_| format!("{} is not valid UTF-8", source))?;
+
+    let rom = asm::assemble(&text).map_err(|err| format!("{}:{}", source, err))?;
+
+    File::create(&output)
+        .and_then(|mut file| file.write_all(&rom))
+        .map_err(|err| format!("could not write {}: {}", output, err))?;
+
+    println!("wrote {} bytes to {}", rom.len(), output);
+
+    Ok(())
+}
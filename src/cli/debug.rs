@@ -0,0 +1,298 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::time::Duration;
+
+use crossterm::event::{self, Event, KeyCode, KeyModifiers};
+use crossterm::terminal;
+
+use chip8::chip8::PROGRAM_START;
+use chip8::debugger::{Register, Stop};
+use chip8::disasm::Item;
+use chip8::octo::Program;
+use chip8::{Chip8, Config, Debugger};
+
+/// Bytes shown by `x` without a length.
+const EXAMINE_LENGTH: usize = 64;
+
+/// Instructions shown by `list` without a count, a few of them before PC.
+const LIST_LENGTH: usize = 12;
+const LIST_BEFORE: u16 = 4;
+
+const HELP: &str = "\
+step [n]               s   execute n instructions, 1 without n
+continue               c   run until a breakpoint, a key wait or a halt, esc or ctrl-c breaks
+break [addr]           b   set a breakpoint, list them without an address
+delete [addr]          d   clear a breakpoint, all of them without an address
+regs                   r   print the registers, stack, timers and keys
+x <addr> [length]          examine memory
+write <addr> <byte>..  w   modify memory
+set <reg> <value>          modify V0-VF, I, PC, SP, DT or ST
+list [addr] [count]    l   disassemble, around PC without an address
+key <hex> [down|up]    k   press or release a key of the keypad
+quit                   q
+an empty line repeats step and continue, addresses can be labels of .8o sources";
+
+/// A debugger prompt for a ROM or Octo source.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut path = None;
+    let mut config = Config::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(super::usage);
+
+        match arg.as_str() {
+            "--seed" => config.seed = super::parse_number(value()?)? as u64,
+            "--speed" => config.instructions_per_second = super::parse_number(value()?)?,
+            "--quirks" => config.quirks = super::parse_quirks(value()?)?,
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg.clone()),
+            _ => return Err(super::usage()),
+        }
+    }
+
+    let path = path.ok_or_else(super::usage)?;
+    let (rom, program) = super::load_program(&path)?;
+    let chip8 = Chip8::with_config(&rom, config).map_err(|err| format!("could not load {}: {}", path, err))?;
+
+    let mut session = Session { debugger: Debugger::new(chip8), program };
+    if let Some(ref program) = session.program {
+        for (&address, name) in &program.breakpoints {
+            session.debugger.add_breakpoint(address);
+            println!("breakpoint {} at 0x{:03X}", name, address);
+        }
+    }
+    println!("loaded {}, type help for the commands", path);
+    session.show_pc();
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut last = String::new();
+    loop {
+        print!("(chip8) ");
+        io::stdout().flush().map_err(|err| err.to_string())?;
+
+        let line = match lines.next() {
+            Some(line) => line.map_err(|err| err.to_string())?,
+            None => break,
+        };
+        let line = match line.trim() {
+            "" => last.clone(),
+            line => line.to_string(),
+        };
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match session.execute(&words) {
+            Ok(true) => (),
+            Ok(false) => break,
+            Err(err) => println!("{}", err),
+        }
+
+        if let Some(&("s" | "step" | "c" | "continue")) = words.first() {
+            last = line;
+        } else {
+            last.clear();
+        }
+    }
+
+    Ok(())
+}
+
+struct Session {
+    debugger: Debugger,
+    program: Option<Program>,
+}
+
+impl Session {
+    /// Runs one command, returns false to quit.
+    fn execute(&mut self, words: &[&str]) -> Result<bool, String> {
+        let (command, args) = match words.split_first() {
+            Some((&command, args)) => (command, args),
+            None => return Ok(true),
+        };
+
+        match (command, args) {
+            ("s" | "step", []) => {
+                let stop = self.debugger.step(1);
+                self.report(stop);
+            }
+            ("s" | "step", [count]) => {
+                let stop = self.debugger.step(super::parse_number(count)? as u64);
+                self.report(stop);
+            }
+            ("c" | "continue", []) => {
+                let stop = continue_until_interrupt(&mut self.debugger).map_err(|err| err.to_string())?;
+                self.report(stop);
+            }
+            ("b" | "break", []) => {
+                for &address in self.debugger.breakpoints() {
+                    println!("0x{:03X}{}", address, self.describe(address, " (", ")"));
+                }
+            }
+            ("b" | "break", [address]) => {
+                let address = self.address(address)?;
+                if self.debugger.add_breakpoint(address) {
+                    println!("breakpoint at 0x{:03X}{}", address, self.describe(address, " (", ")"));
+                }
+            }
+            ("d" | "delete", []) => self.debugger.clear_breakpoints(),
+            ("d" | "delete", [address]) => {
+                let address = self.address(address)?;
+                if !self.debugger.remove_breakpoint(address) {
+                    return Err(format!("no breakpoint at 0x{:03X}", address));
+                }
+            }
+            ("r" | "regs", []) => self.print_registers(),
+            ("x", [address]) => self.examine(self.address(address)?, EXAMINE_LENGTH),
+            ("x", [address, length]) => self.examine(self.address(address)?, super::parse_number(length)? as usize),
+            ("w" | "write", [address, bytes @ ..]) if !bytes.is_empty() => {
+                let address = self.address(address)?;
+                let bytes = bytes.iter().map(|byte| match super::parse_number(byte)? {
+                    byte if byte <= 0xFF => Ok(byte as u8),
+                    _ => Err(format!("not a byte: {}", byte)),
+                });
+                let bytes = bytes.collect::<Result<Vec<u8>, String>>()?;
+                if !self.debugger.write_memory(address, &bytes) {
+                    return Err(format!("{} bytes at 0x{:03X} do not fit into memory", bytes.len(), address));
+                }
+            }
+            ("set", [register, value]) => {
+                let register = Register::parse(register).ok_or_else(|| format!("unknown register {}", register))?;
+                let value = self.address(value)?;
+                self.debugger.set_register(register, value)?;
+            }
+            ("l" | "list", []) => {
+                //the font below the program is no code worth showing
+                let pc = self.debugger.register(Register::Pc);
+                let start = match pc.saturating_sub(2 * LIST_BEFORE) {
+                    start if pc >= PROGRAM_START as u16 => start.max(PROGRAM_START as u16),
+                    start => start,
+                };
+                self.list(start, LIST_LENGTH);
+            }
+            ("l" | "list", [address]) => self.list(self.address(address)?, LIST_LENGTH),
+            ("l" | "list", [address, count]) => self.list(self.address(address)?, super::parse_number(count)? as usize),
+            ("k" | "key", [key]) => self.press(key, "down")?,
+            ("k" | "key", [key, state]) => self.press(key, state)?,
+            ("h" | "help" | "?", []) => println!("{}", HELP),
+            ("q" | "quit", []) => return Ok(false),
+            _ => return Err(format!("invalid command {}, type help for the commands", words.join(" "))),
+        }
+
+        Ok(true)
+    }
+
+    fn report(&self, stop: Stop) {
+        match stop {
+            Stop::Stepped => (),
+            Stop::Breakpoint(address) => println!("breakpoint at 0x{:03X}{}", address, self.describe(address, " (", ")")),
+            Stop::WaitingForKey => println!("waiting for a key, press one with `key <hex>`"),
+            Stop::Halted => println!("halted, the program jumps to itself"),
+            Stop::Interrupted => println!("interrupted"),
+            Stop::Fault(err) => match self.program {
+                Some(ref program) => println!("{} ({})", err, program.locate(err.address())),
+                None => println!("{}", err),
+            },
+        }
+        self.show_pc();
+    }
+
+    fn show_pc(&self) {
+        let pc = self.debugger.register(Register::Pc);
+        self.list(pc, 1);
+    }
+
+    fn print_registers(&self) {
+        println!("{}", self.debugger.chip8());
+    }
+
+    fn examine(&self, address: u16, length: usize) {
+        for (row, bytes) in self.debugger.memory(address, length).chunks(16).enumerate() {
+            let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            println!("0x{:03X}  {}", address as usize + row * 16, bytes.join(" "));
+        }
+    }
+
+    fn list(&self, address: u16, count: usize) {
+        let pc = self.debugger.register(Register::Pc);
+        let listing = self.debugger.disassemble(address, count);
+
+        for line in &listing.lines {
+            if let Some(ref label) = line.label {
+                println!("   {}:", label);
+            }
+
+            let marker = match (line.address == pc, self.debugger.breakpoints().contains(&line.address)) {
+                (true, _) => "=>",
+                (false, true) => " *",
+                (false, false) => "  ",
+            };
+            let bytes: Vec<String> = line.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            let text = match line.item {
+                Item::Code(ref instruction) => listing.mnemonic(instruction),
+                Item::Sprite(byte) => format!("DB 0x{:02X}", byte),
+                Item::Data(ref bytes) => format!("DB 0x{:02X}", bytes[0]),
+            };
+            let text = format!("{} 0x{:03X}  {:<5} {:<20}{}", marker, line.address, bytes.join(""), text, self.describe(line.address, "; ", ""));
+            println!("{}", text.trim_end());
+        }
+    }
+
+    fn press(&mut self, key: &str, state: &str) -> Result<(), String> {
+        let key = match u8::from_str_radix(key, 16) {
+            Ok(key) if key <= 0xF => key,
+            _ => return Err(format!("invalid key {}", key)),
+        };
+        match state {
+            "down" => self.debugger.chip8_mut().key_down(key),
+            "up" => self.debugger.chip8_mut().key_up(key),
+            _ => return Err(format!("expected down or up, found {}", state)),
+        }
+        Ok(())
+    }
+
+    /// A number or, for Octo sources, a label or breakpoint name.
+    fn address(&self, text: &str) -> Result<u16, String> {
+        if let Some(ref program) = self.program {
+            let breakpoint = program.breakpoints.iter().find(|&(_, name)| name == text).map(|(&address, _)| address);
+            if let Some(address) = program.labels.get(text).cloned().or(breakpoint) {
+                return Ok(address);
+            }
+        }
+        match super::parse_number(text)? {
+            address if address <= 0xFFFF => Ok(address as u16),
+            _ => Err(format!("{} is out of range", text)),
+        }
+    }
+
+    /// The source location of `address` in an Octo source between `open`
+    /// and `close`, empty for ROMs.
+    fn describe(&self, address: u16, open: &str, close: &str) -> String {
+        match self.program {
+            Some(ref program) if program.line_at(address).is_some() => format!("{}{}{}", open, program.locate(address), close),
+            _ => String::new(),
+        }
+    }
+}
+
+/// Continues the program. On a terminal escape or ctrl-c breaks into the
+/// debugger again, piped input can not interrupt.
+fn continue_until_interrupt(debugger: &mut Debugger) -> io::Result<Stop> {
+    if !io::stdin().is_terminal() {
+        return Ok(debugger.run(|| false));
+    }
+
+    terminal::enable_raw_mode()?;
+    let stop = debugger.run(|| {
+        while let Ok(true) = event::poll(Duration::from_secs(0)) {
+            if let Ok(Event::Key(key)) = event::read() {
+                let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                if key.code == KeyCode::Esc || ctrl_c {
+                    return true;
+                }
+            }
+        }
+        false
+    });
+    terminal::disable_raw_mode()?;
+
+    Ok(stop)
+}
@@ -0,0 +1,41 @@
+use chip8::chip8::{MEMORY_SIZE, PROGRAM_START};
+use chip8::disasm;
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut linear = false;
+    let mut origin = PROGRAM_START;
+    let mut path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--linear" => linear = true,
+            "--origin" => {
+                let value = args.next().ok_or_else(super::usage)?;
+                origin = super::parse_number(value)? as usize;
+            }
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(super::usage()),
+        }
+    }
+
+    let path = path.ok_or_else(super::usage)?;
+    let rom = super::read_file(path)?;
+
+    if origin + rom.len() > MEMORY_SIZE {
+        return Err(format!("{} does not fit into memory at 0x{:03X}", path, origin));
+    }
+
+    let listing = if linear {
+        let mut memory = vec![0; origin];
+        memory.extend_from_slice(&rom);
+        disasm::disassemble_memory(&memory, origin, memory.len())
+    } else {
+        disasm::disassemble(&rom, origin as u16).map_err(|err| format!("{}: {}", path, err))?
+    };
+
+    println!("; {}", path);
+    print!("{}", listing);
+
+    Ok(())
+}
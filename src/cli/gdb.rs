@@ -0,0 +1,57 @@
+use std::net::TcpListener;
+
+use chip8::gdb::{GdbServer, SessionEnd};
+use chip8::{Chip8, Config, Debugger};
+
+const DEFAULT_PORT: u16 = 1234;
+
+/// Serves a ROM to gdb on a local port, `target remote :1234` attaches.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut path = None;
+    let mut port = DEFAULT_PORT;
+    let mut config = Config::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(super::usage);
+
+        match arg.as_str() {
+            "--port" => port = match super::parse_number(value()?)? {
+                port if port <= 0xFFFF => port as u16,
+                port => return Err(format!("port {} is out of range", port)),
+            },
+            "--seed" => config.seed = super::parse_number(value()?)? as u64,
+            "--speed" => config.instructions_per_second = super::parse_number(value()?)?,
+            "--quirks" => config.quirks = super::parse_quirks(value()?)?,
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg.clone()),
+            _ => return Err(super::usage()),
+        }
+    }
+
+    let path = path.ok_or_else(super::usage)?;
+    let (rom, _) = super::load_program(&path)?;
+    let chip8 = Chip8::with_config(&rom, config).map_err(|err| format!("could not load {}: {}", path, err))?;
+    let mut server = GdbServer::new(Debugger::new(chip8));
+
+    //only local tools may attach, the stub can rewrite all of memory
+    let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|err| format!("could not listen on port {}: {}", port, err))?;
+    println!("waiting for gdb on 127.0.0.1:{}", port);
+
+    for stream in listener.incoming() {
+        let mut stream = stream.map_err(|err| err.to_string())?;
+        let _ = stream.set_nodelay(true);
+        println!("gdb attached");
+
+        match server.serve(&mut stream) {
+            Ok(SessionEnd::Killed) => {
+                println!("killed");
+                break;
+            }
+            Ok(SessionEnd::Detached) => println!("gdb detached, waiting for the next session"),
+            Ok(SessionEnd::Disconnected) => println!("gdb disconnected, waiting for the next session"),
+            Err(err) => println!("connection failed: {}, waiting for the next session", err),
+        }
+    }
+
+    Ok(())
+}
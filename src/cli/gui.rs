@@ -0,0 +1,245 @@
+use chip8::{Chip8, Config, DisplaySink, Driver, DriverError, Input, InputSource, Keymap, Rewind};
+use chip8::frontend::Realtime;
+
+use self::CanvasId::*;
+use nwg;
+use nwg::{Event, EventArgs, Ui,fatal_message,dispatch_events,Timer};
+use nwg::constants as nwgc;
+
+use std::sync::mpsc::TryRecvError::Disconnected;
+use std::sync::mpsc::TryRecvError::Empty;
+use std::sync::mpsc::channel;
+use std::sync::mpsc::Sender;
+use std::sync::mpsc::Receiver;
+
+use std::io;
+use std::thread;
+
+/// Holding backspace steps the game backwards in time.
+const REWIND_KEY: char = '\u{8}';
+
+const REWIND_SECONDS: u32 = 10;
+
+#[derive(Debug,Clone,Hash)]
+pub enum CanvasId {
+    MainWindow,
+    Canvas,
+    Paint,
+    KeyDown,
+    KeyUp,
+    TimeGfx,
+    KeyEventTx,
+    GfxRx,
+    SolidBrush(u8),
+}
+
+
+
+nwg_template!(
+    head: setup_ui<CanvasId>,
+    controls: [
+        (MainWindow, nwg_window!( title="Chip8"; size=(640,320); resizable=false)),
+        (Canvas, nwg_canvas!(parent=MainWindow; size=(640,320))),
+        (TimeGfx, nwg_timer!(interval=1))
+    ];
+
+    events:[
+
+        (TimeGfx, TimeGfx, Event::Tick, |app,_,_,_| {
+            app.trigger(&Canvas,Event::Paint,EventArgs::None);
+        }),
+
+
+        (Canvas, Paint, Event::Paint, | app,_,_,_| {         
+            let mut canvas =nwg_get_mut!(app; (Canvas,nwg::Canvas<CanvasId>));
+            let mut timer = nwg_get_mut!(app; (TimeGfx,Timer));
+
+            let gfx_rx = nwg_get_mut!(app;(GfxRx, Receiver<[u8;64*32]>));
+            
+            if !timer.running(){
+                timer.start();
+            }
+
+            //only frames that changed are sent, keep the old one until then
+            let gfx = match gfx_rx.try_recv(){
+                Ok(gfx) => gfx,
+                Err(err) => match err {
+                    Empty=> return,
+                    Disconnected => panic!("disconnected"),
+                },
+            };
+
+            let mut renderer =canvas.renderer().unwrap();
+
+            renderer.clear(0.3,0.3,0.6,1.0);
+
+            for row in 0..32{
+                for col in 0..64{
+                    if gfx[row*64+col] != 0 { 
+           
+                        let left = (col*10) as f32;
+                        let top = (row*10) as f32;
+                        let right = left + 10f32;
+                        let bottom = top + 10f32;
+
+                        let rect = nwgc::Rectangle{ left:left, right:right, top:top, bottom:bottom };
+                        renderer.draw_rectangle(&SolidBrush(0),None,&rect,1.0).unwrap();
+                    }
+                }
+            }
+
+
+        }),
+
+        (MainWindow, KeyDown,Event::KeyDown, |app,_,_,args| {
+            send_key(app, args, true);
+        }),
+
+        (MainWindow, KeyUp,Event::KeyUp, |app,_,_,args| {
+            send_key(app, args, false);
+        })
+
+    ];
+    resources:[];
+
+    values: []
+
+);
+
+/// Turns the keys sent by the window's key events into inputs.
+struct GuiInput {
+    key_rx: Receiver<(char, bool)>,
+    keymap: Keymap,
+}
+
+impl InputSource for GuiInput {
+    fn poll(&mut self, _frame: u64) -> io::Result<Vec<Input>> {
+        let mut inputs = Vec::new();
+
+        while let Ok((key, pressed)) = self.key_rx.try_recv() {
+            if key == REWIND_KEY {
+                inputs.push(Input::Rewind(pressed));
+            } else if let Some(hex) = self.keymap.key(key) {
+                inputs.push(Input::key(hex, pressed));
+            }
+        }
+
+        Ok(inputs)
+    }
+}
+
+/// Sends the frames that changed to the UI thread.
+struct GuiDisplay {
+    gfx_tx: Sender<[u8;64*32]>,
+}
+
+impl DisplaySink for GuiDisplay {
+    fn present(&mut self, gfx: &[u8], _frame: u64, changed: bool) -> io::Result<()> {
+        if changed {
+            let mut frame = [0u8;64*32];
+            frame.copy_from_slice(gfx);
+            if self.gfx_tx.send(frame).is_err() {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "the window was closed"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Forwards a key going down or up to the emulation thread.
+fn send_key(app: &Ui<CanvasId>, args: &EventArgs, pressed: bool) {
+    match args{
+        &EventArgs::Key(k) => {
+            let key = ((k as u8) as char).to_lowercase().next().unwrap();
+            let key_tx = nwg_get_mut!(app;(KeyEventTx, Sender<(char, bool)>));
+            match key_tx.send((key, pressed)) {
+                Ok(_) => (),
+                Err(err) => panic!(err),
+            };
+        },
+        _ => println!("not a key"),
+    }
+}
+
+fn setup_canvas_resources(app: &Ui<CanvasId>){
+    let mut canvas = nwg_get_mut!(app; (Canvas, nwg::Canvas<CanvasId>));
+
+    let b1 = nwgc::SolidBrush{color:(1.0, 1.0, 1.0, 1.0)};
+    let b2 = nwgc::SolidBrush{color:(0.0, 0.0, 0.0, 1.0)};
+    
+    canvas.create_solid_brush(&SolidBrush(0), &b1).expect("Failed to create brush 1");
+    canvas.create_solid_brush(&SolidBrush(1), &b2).expect("Failed to create brush 22");
+}
+
+
+
+pub fn run(args: &[String]) -> Result<(), String> {
+
+    let mut positional = Vec::new();
+    let mut keymap = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--keymap" => keymap = Some(args.next().ok_or_else(super::usage)?.clone()),
+            _ if !arg.starts_with("--") => positional.push(arg),
+            _ => return Err(super::usage()),
+        }
+    }
+    if positional.is_empty() || positional.len() > 2 {
+        return Err(super::usage());
+    }
+
+    let path = positional[0];
+    println!("loading {}", path);
+
+    //Octo sources are compiled on the fly, errors then point at source lines
+    let (rom, program) = super::load_program(path)?;
+    let keymap = super::load_keymap(keymap.as_deref(), path, &rom)?;
+
+    //a new seed every start, so MAZE does not draw the same maze each time
+    let config = Config{ seed: super::time_seed(), ..Config::default() };
+    let mut chip8 = Chip8::with_config(&rom, config).map_err(|err| format!("could not load {}: {}", path, err))?;
+
+    if let Some(speed) = positional.get(1) {
+        chip8.set_instructions_per_second(super::parse_number(speed)?);
+    }
+
+    let app: Ui<CanvasId>;
+
+    match Ui::new(){
+        Ok(_app) => {app = _app},
+        Err(e) => fatal_message("Fatal Error", &format!("{:?}",e)),
+    };
+
+    let (key_tx,key_rx): (Sender<(char, bool)>, Receiver<(char, bool)>) = channel();
+    let (gfx_tx,gfx_rx): (Sender<[u8;64*32]>, Receiver<[u8;64*32]>) = channel();
+
+    app.pack_value(&KeyEventTx,key_tx);
+    app.pack_value(&GfxRx, gfx_rx);
+
+    thread::spawn(move || {
+        //one frame per 60 Hz tick, the UI only receives frames that changed
+        let input = GuiInput{ key_rx: key_rx, keymap: keymap };
+        let display = GuiDisplay{ gfx_tx: gfx_tx };
+        let mut driver = Driver::new(display, input, Realtime::new()).with_rewind(Rewind::new(REWIND_SECONDS));
+
+        if let Err(err) = driver.run(&mut chip8) {
+            let message = match (err, program) {
+                (DriverError::Emulation(err), Some(ref program)) => format!("{} ({})", err, program.locate(err.address())),
+                (err, _) => format!("{}", err),
+            };
+            fatal_message("Emulation Error", &message)
+        }
+    });
+
+    if let Err(e) = setup_ui(&app) {
+        fatal_message("Fatal Error", &format!("{:?}",e));
+    }
+
+    setup_canvas_resources(&app);
+    dispatch_events();
+
+    Ok(())
+}
+
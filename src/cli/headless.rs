@@ -0,0 +1,359 @@
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use chip8::frontend::Unthrottled;
+use chip8::audio;
+use chip8::screenshot;
+use chip8::{Chip8, Config, DisplaySink, Driver, DriverError, KeyEvent, KeyScript, Movie, Player, SquareWave, TraceFormat, TraceWriter};
+
+/// Ten seconds, when neither `--frames` nor a movie says otherwise.
+const DEFAULT_FRAMES: u64 = 600;
+
+/// 4096 x 2048 pixels, anything larger is most likely a typo.
+const MAX_SCALE: u32 = 64;
+
+/// Sample rate of the `--wav` output, 735 samples per frame.
+const SAMPLE_RATE: u32 = 44100;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Pbm,
+    Png,
+}
+
+struct Options {
+    path: String,
+    frames: Option<u64>,
+    dumps: BTreeSet<u64>,
+    dump_end: bool,
+    format: Format,
+    scale: usize,
+    prefix: Option<String>,
+    keys: Option<String>,
+    play: Option<String>,
+    record: Option<String>,
+    trace: Option<String>,
+    trace_format: TraceFormat,
+    trace_addresses: Option<(u16, u16)>,
+    trace_cycles: Option<(u64, u64)>,
+    wav: Option<String>,
+    config: Config,
+}
+
+/// Runs a ROM without any window and writes what it drew to image files.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+
+    let (rom, program) = super::load_program(&options.path)?;
+
+    let player = match options.play {
+        Some(ref path) => {
+            let text = String::from_utf8(super::read_file(path)?)
+                .map_err(|_| format!("{} is not valid UTF-8", path))?;
+            let movie = Movie::parse(&text).map_err(|err| format!("{}: {}", path, err))?;
+            let player = Player::new(movie);
+            player.check_rom(&rom).map_err(|err| err.to_string())?;
+            Some(player)
+        }
+        None => None,
+    };
+
+    let config = player.as_ref().map_or(options.config, |player| player.movie().config);
+    let frames = options.frames.unwrap_or_else(|| {
+        player.as_ref().map_or(DEFAULT_FRAMES, |player| player.movie().frames)
+    });
+
+    let mut events = match options.keys {
+        Some(ref path) => parse_script(path)?,
+        None => Vec::new(),
+    };
+    if let Some(ref player) = player {
+        events.extend_from_slice(&player.movie().events);
+    }
+
+    let mut chip8 = Chip8::with_config(&rom, config)
+        .map_err(|err| format!("could not load {}: {}", options.path, err))?;
+    if options.record.is_some() {
+        chip8.start_recording();
+    }
+
+    let trace = match options.trace {
+        Some(ref path) => {
+            let trace = Arc::new(Mutex::new(open_trace(path, &options)?));
+            chip8.set_trace_sink(Some(Box::new(trace.clone())));
+            Some(trace)
+        }
+        None => None,
+    };
+
+    let buzzer = match options.wav {
+        Some(_) => {
+            let buzzer = Arc::new(Mutex::new(SquareWave::new(SAMPLE_RATE)));
+            chip8.set_audio_sink(Box::new(buzzer.clone()));
+            Some(buzzer)
+        }
+        None => None,
+    };
+
+    let prefix = options.prefix.clone().unwrap_or_else(|| {
+        Path::new(&options.path).with_extension("").to_string_lossy().into_owned()
+    });
+
+    let sink = DumpSink { prefix, options: &options, dumped: None, buzzer, samples: Vec::new() };
+    let mut driver = Driver::new(sink, KeyScript::new(events), Unthrottled).with_frame_limit(frames);
+
+    let failure = match driver.run(&mut chip8) {
+        Ok(()) => None,
+        Err(DriverError::Io(err)) => return Err(err.to_string()),
+        Err(DriverError::Emulation(err)) => {
+            let location = match program {
+                Some(ref program) => format!(" ({})", program.locate(err.address())),
+                None => String::new(),
+            };
+            Some(format!("frame {}: {}{}", chip8.frame_count(), err, location))
+        }
+    };
+
+    //the screen at a fault is the most interesting one, always keep it
+    let sink = &mut driver.display;
+    if (options.dump_end || failure.is_some()) && sink.dumped != Some(chip8.frame_count()) {
+        sink.dump(chip8.get_gfx(), chip8.frame_count()).map_err(|err| err.to_string())?;
+    }
+
+    if let Some(ref path) = options.wav {
+        File::create(path)
+            .and_then(|file| audio::write_wav(&sink.samples, SAMPLE_RATE, BufWriter::new(file)))
+            .map_err(|err| format!("could not write {}: {}", path, err))?;
+    }
+
+    if let Some(trace) = trace {
+        chip8.set_trace_sink(None);
+        let path = options.trace.as_deref().unwrap_or("");
+        let mut trace = trace.lock().map_err(|_| "the trace was poisoned".to_string())?;
+        trace.finish().map_err(|err| format!("could not write {}: {}", path, err))?;
+    }
+
+    if let Some(ref path) = options.record {
+        let movie = chip8.stop_recording(&rom);
+        File::create(path)
+            .and_then(|mut file| file.write_all(movie.to_string().as_bytes()))
+            .map_err(|err| format!("could not write {}: {}", path, err))?;
+    }
+
+    if let Some(err) = failure {
+        return Err(err);
+    }
+
+    if let Some(ref player) = player {
+        if chip8.frame_count() == player.movie().frames && !player.matches(&chip8) {
+            return Err("the display differs from the end of the movie".to_string());
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the frames listed with `--dump` as they are presented.
+struct DumpSink<'a> {
+    prefix: String,
+    options: &'a Options,
+    /// The last frame written, so the end is not written twice.
+    dumped: Option<u64>,
+    /// The tone for `--wav`, rendered a frame at a time into `samples`.
+    buzzer: Option<Arc<Mutex<SquareWave>>>,
+    samples: Vec<i16>,
+}
+
+impl<'a> DumpSink<'a> {
+    fn dump(&mut self, gfx: &[u8], frame: u64) -> io::Result<()> {
+        let extension = match self.options.format {
+            Format::Pbm => "pbm",
+            Format::Png => "png",
+        };
+        let path = format!("{}-{:06}.{}", self.prefix, frame, extension);
+
+        let name = |err: io::Error| io::Error::new(err.kind(), format!("could not write {}: {}", path, err));
+        let out = BufWriter::new(File::create(&path).map_err(name)?);
+        match self.options.format {
+            Format::Pbm => screenshot::write_pbm(gfx, out),
+            Format::Png => screenshot::write_png(gfx, self.options.scale, out),
+        }.map_err(name)?;
+
+        println!("wrote {}", path);
+        self.dumped = Some(frame);
+
+        Ok(())
+    }
+}
+
+impl<'a> DisplaySink for DumpSink<'a> {
+    fn present(&mut self, gfx: &[u8], frame: u64, _changed: bool) -> io::Result<()> {
+        //frame 0 is the screen before anything ran
+        if let (Some(buzzer), true) = (self.buzzer.as_ref(), frame > 0) {
+            let start = self.samples.len();
+            self.samples.resize(start + (SAMPLE_RATE / 60) as usize, 0);
+            if let Ok(mut buzzer) = buzzer.lock() {
+                buzzer.render(&mut self.samples[start..]);
+            }
+        }
+        if self.options.dumps.contains(&frame) {
+            self.dump(gfx, frame)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        path: String::new(),
+        frames: None,
+        dumps: BTreeSet::new(),
+        dump_end: false,
+        format: Format::Pbm,
+        scale: 1,
+        prefix: None,
+        keys: None,
+        play: None,
+        record: None,
+        trace: None,
+        trace_format: TraceFormat::Text,
+        trace_addresses: None,
+        trace_cycles: None,
+        wav: None,
+        config: Config::default(),
+    };
+    let mut path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(super::usage);
+
+        match arg.as_str() {
+            "--frames" => options.frames = Some(super::parse_number(value()?)? as u64),
+            "--dump" => {
+                for frame in value()?.split(',') {
+                    if frame == "end" {
+                        options.dump_end = true;
+                    } else {
+                        options.dumps.insert(super::parse_number(frame)? as u64);
+                    }
+                }
+            }
+            "--format" => options.format = match value()?.as_str() {
+                "pbm" => Format::Pbm,
+                "png" => Format::Png,
+                other => return Err(format!("unknown image format: {}", other)),
+            },
+            "--scale" => options.scale = match super::parse_number(value()?)? {
+                scale @ 1..=MAX_SCALE => scale as usize,
+                scale => return Err(format!("the scale has to be between 1 and {}, not {}", MAX_SCALE, scale)),
+            },
+            "--wav" => options.wav = Some(value()?.clone()),
+            "--out" => options.prefix = Some(value()?.clone()),
+            "--keys" => options.keys = Some(value()?.clone()),
+            "--play" => options.play = Some(value()?.clone()),
+            "--record" => options.record = Some(value()?.clone()),
+            "--trace" => options.trace = Some(value()?.clone()),
+            "--trace-format" => options.trace_format = match value()?.as_str() {
+                "text" => TraceFormat::Text,
+                "jsonl" => TraceFormat::JsonLines,
+                "csv" => TraceFormat::Csv,
+                other => return Err(format!("unknown trace format: {}", other)),
+            },
+            "--trace-pc" => {
+                let (start, end) = parse_range(value()?, 0xFFFF)?;
+                options.trace_addresses = Some((start as u16, end as u16));
+            }
+            "--trace-cycles" => options.trace_cycles = Some(parse_range(value()?, u64::MAX)?),
+            "--seed" => options.config.seed = super::parse_number(value()?)? as u64,
+            "--speed" => options.config.instructions_per_second = super::parse_number(value()?)?,
+            "--quirks" => options.config.quirks = super::parse_quirks(value()?)?,
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg.clone()),
+            _ => return Err(super::usage()),
+        }
+    }
+
+    options.path = path.ok_or_else(super::usage)?;
+    if options.dumps.is_empty() && options.trace.is_none() {
+        options.dump_end = true;
+    }
+
+    Ok(options)
+}
+
+/// The trace writer for `--trace`, `-` writes to stdout.
+fn open_trace(path: &str, options: &Options) -> Result<TraceWriter<Box<dyn Write + Send>>, String> {
+    let out: Box<dyn Write + Send> = if path == "-" {
+        Box::new(BufWriter::new(io::stdout()))
+    } else {
+        let file = File::create(path).map_err(|err| format!("could not write {}: {}", path, err))?;
+        Box::new(BufWriter::new(file))
+    };
+
+    let mut trace = TraceWriter::new(out, options.trace_format);
+    if let Some((start, end)) = options.trace_addresses {
+        trace = trace.with_addresses(start..=end);
+    }
+    if let Some((start, end)) = options.trace_cycles {
+        trace = trace.with_cycles(start..=end);
+    }
+
+    Ok(trace)
+}
+
+/// Parses an inclusive range like `0x200-0x2FF` up to `max`. Either end
+/// may be left out and a single number is a range of its own.
+fn parse_range(text: &str, max: u64) -> Result<(u64, u64), String> {
+    let (start, end) = match text.find('-') {
+        Some(dash) => (&text[..dash], &text[dash + 1..]),
+        None => (text, text),
+    };
+    let start = if start.is_empty() { 0 } else { super::parse_number(start)? as u64 };
+    let end = if end.is_empty() { max } else { super::parse_number(end)? as u64 };
+
+    if end > max {
+        return Err(format!("{} is out of range", text));
+    }
+    if start > end {
+        return Err(format!("empty range: {}", text));
+    }
+    Ok((start, end))
+}
+
+/// Reads a key script, one `<frame> <key> down|up` event per line, keys
+/// in hex. `#` starts a comment.
+fn parse_script(path: &str) -> Result<Vec<KeyEvent>, String> {
+    let text = String::from_utf8(super::read_file(path)?)
+        .map_err(|_| format!("{} is not valid UTF-8", path))?;
+
+    let mut events = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let error = |message: String| format!("{}:{}: {}", path, index + 1, message);
+
+        let code = line.split('#').next().unwrap_or("");
+        let words: Vec<&str> = code.split_whitespace().collect();
+        let (frame, key, state) = match words.as_slice() {
+            [] => continue,
+            [frame, key, state] => (frame, key, state),
+            _ => return Err(error("expected <frame> <key> down|up".to_string())),
+        };
+
+        let frame = super::parse_number(frame).map_err(&error)? as u64;
+        let key = match u8::from_str_radix(key, 16) {
+            Ok(key) if key <= 0xF => key,
+            _ => return Err(error(format!("invalid key {}", key))),
+        };
+        let pressed = match *state {
+            "down" => true,
+            "up" => false,
+            _ => return Err(error(format!("expected down or up, found {}", state))),
+        };
+
+        events.push(KeyEvent { frame, key, pressed });
+    }
+
+    Ok(events)
+}
@@ -0,0 +1,121 @@
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chip8::octo::Program;
+use chip8::{Keymap, Keymaps, Quirks};
+
+pub mod asm;
+pub mod debug;
+pub mod disasm;
+pub mod gdb;
+pub mod headless;
+pub mod octo;
+pub mod replay;
+pub mod term;
+
+#[cfg(feature = "gui")]
+pub mod gui;
+
+#[cfg(not(feature = "gui"))]
+pub mod gui {
+    pub fn run(_args: &[String]) -> Result<(), String> {
+        Err("this build has no window frontend, rebuild with `--features gui`".to_string())
+    }
+}
+
+pub fn usage() -> String {
+    [
+        "usage:",
+        "  chip8 [run] <rom or .8o source> [instructions per second] [--keymap <layout|file>]",
+        "  chip8 disasm [--linear] [--origin <address>] <rom>",
+        "  chip8 asm <source> [-o <rom>]",
+        "  chip8 octo <source> [-o <rom>] [--symbols <file>]",
+        "  chip8 term <rom> [--braille] [--fg <color>] [--bg <color>] [--seed <n>] [--speed <n>]",
+        "             [--quirks <preset|names>] [--keymap <layout|file>]",
+        "  chip8 replay <rom> <movie>",
+        "  chip8 debug <rom or .8o source> [--seed <n>] [--speed <n>] [--quirks <preset|names>]",
+        "  chip8 gdb <rom or .8o source> [--port <n>] [--seed <n>] [--speed <n>] [--quirks <preset|names>]",
+        "  chip8 headless <rom> [--frames <n>] [--dump <frame,...|end>] [--format pbm|png]",
+        "                 [--scale <n>] [--out <prefix>] [--keys <script>] [--play <movie>]",
+        "                 [--record <movie>] [--seed <n>] [--speed <n>] [--quirks <preset|names>]",
+        "                 [--trace <file|->] [--trace-format text|jsonl|csv] [--trace-pc <from-to>]",
+        "                 [--trace-cycles <from-to>] [--wav <file>]",
+        "",
+        "keymaps: qwertz (default), qwerty, azerty or a keymap file, $CHIP8_KEYMAP sets the default",
+    ].join("\n")
+}
+
+pub fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .map_err(|err| format!("could not read {}: {}", path, err))?;
+
+    Ok(bytes)
+}
+
+/// Reads a ROM, Octo sources (`.8o`) are compiled and come with their
+/// source map.
+pub fn load_program(path: &str) -> Result<(Vec<u8>, Option<Program>), String> {
+    if path.ends_with(".8o") {
+        let program = octo::compile(path)?;
+        Ok((program.rom.clone(), Some(program)))
+    } else {
+        Ok((read_file(path)?, None))
+    }
+}
+
+/// The keymap for the ROM at `path`. `choice` is a layout name or a keymap
+/// file, without one `$CHIP8_KEYMAP` is used.
+pub fn load_keymap(choice: Option<&str>, path: &str, rom: &[u8]) -> Result<Keymap, String> {
+    let choice = match choice.map(str::to_string).or_else(|| env::var("CHIP8_KEYMAP").ok()) {
+        Some(choice) => choice,
+        None => return Ok(Keymap::default()),
+    };
+    if let Some(keymap) = Keymap::preset(&choice) {
+        return Ok(keymap);
+    }
+
+    let text = String::from_utf8(read_file(&choice)?).map_err(|_| format!("{} is not valid UTF-8", choice))?;
+    let keymaps = Keymaps::parse(&text).map_err(|err| format!("{}: {}", choice, err))?;
+    let name = Path::new(path).file_stem().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+
+    Ok(keymaps.keymap(&name, rom))
+}
+
+/// Parses a quirks preset like `vip` or a comma separated list of quirk names.
+pub fn parse_quirks(text: &str) -> Result<Quirks, String> {
+    if let Some(quirks) = Quirks::preset(text) {
+        return Ok(quirks);
+    }
+
+    let mut quirks = Quirks::default();
+    for name in text.split(',') {
+        if !quirks.set(name, true) {
+            return Err(format!("unknown quirk or preset: {}", name));
+        }
+    }
+
+    Ok(quirks)
+}
+
+/// A different seed on every start, so MAZE does not draw the same maze
+/// each time.
+pub fn time_seed() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_nanos() as u64).unwrap_or(0)
+}
+
+/// Parses decimal or `0x` prefixed hexadecimal numbers.
+pub fn parse_number(text: &str) -> Result<u32, String> {
+    let parsed = if text.starts_with("0x") || text.starts_with("0X") {
+        u32::from_str_radix(&text[2..], 16)
+    } else {
+        text.parse()
+    };
+
+    parsed.map_err(|_| format!("invalid number: {}", text))
+}
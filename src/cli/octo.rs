@@ -0,0 +1,51 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use chip8::octo;
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut source = None;
+    let mut output = None;
+    let mut symbols = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(args.next().ok_or_else(super::usage)?.clone()),
+            "--symbols" => symbols = Some(args.next().ok_or_else(super::usage)?.clone()),
+            _ if source.is_none() => source = Some(arg),
+            _ => return Err(super::usage()),
+        }
+    }
+
+    let source = source.ok_or_else(super::usage)?;
+    let output = output.unwrap_or_else(|| {
+        Path::new(source).with_extension("ch8").to_string_lossy().into_owned()
+    });
+    let symbols = symbols.unwrap_or_else(|| {
+        Path::new(&output).with_extension("sym").to_string_lossy().into_owned()
+    });
+
+    let program = compile(source)?;
+
+    File::create(&output)
+        .and_then(|mut file| file.write_all(&program.rom))
+        .map_err(|err| format!("could not write {}: {}", output, err))?;
+
+    File::create(&symbols)
+        .and_then(|file| program.write_symbols(file))
+        .map_err(|err| format!("could not write {}: {}", symbols, err))?;
+
+    println!("wrote {} bytes to {}, symbols to {}", program.rom.len(), output, symbols);
+
+    Ok(())
+}
+
+/// Reads and compiles an Octo source file.
+pub fn compile(path: &str) -> Result<octo::Program, String> {
+    let text = String::from_utf8(super::read_file(path)?)
+        .map_err(|_| format!("{} is not valid UTF-8", path))?;
+
+    octo::compile(&text).map_err(|err| format!("{}:{}", path, err))
+}
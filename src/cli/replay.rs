@@ -0,0 +1,31 @@
+use chip8::frontend::Unthrottled;
+use chip8::{Chip8, Driver, Movie, Player};
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    if args.len() != 2 {
+        return Err(super::usage());
+    }
+
+    let rom = super::read_file(&args[0])?;
+    let text = String::from_utf8(super::read_file(&args[1])?)
+        .map_err(|_| format!("{} is not valid UTF-8", args[1]))?;
+    let movie = Movie::parse(&text).map_err(|err| format!("{}: {}", args[1], err))?;
+
+    let player = Player::new(movie);
+    player.check_rom(&rom).map_err(|err| err.to_string())?;
+
+    let mut chip8 = Chip8::with_config(&rom, player.movie().config)
+        .map_err(|err| format!("could not load {}: {}", args[0], err))?;
+
+    let frames = player.movie().frames;
+    let mut driver = Driver::new((), player, Unthrottled).with_frame_limit(frames);
+    driver.run(&mut chip8).map_err(|err| format!("frame {}: {}", chip8.frame_count(), err))?;
+
+    if !driver.input.matches(&chip8) {
+        return Err(format!("replayed {} frames, but the display differs from the recording", chip8.frame_count()));
+    }
+
+    println!("replayed {} frames, the display matches the recording", chip8.frame_count());
+
+    Ok(())
+}
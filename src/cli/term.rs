@@ -0,0 +1,309 @@
+use std::io::{self, Stdout, Write};
+use std::time::Duration;
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags};
+use crossterm::event::{PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::QueueableCommand;
+
+use chip8::chip8::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use chip8::frontend::Realtime;
+use chip8::{AudioSink, Chip8, Config, DisplaySink, Driver, DriverError, Input, InputSource, Keymap};
+
+/// Without release events from the terminal a key counts as held for this
+/// many frames after its last press or auto repeat.
+const KEY_HOLD_FRAMES: u64 = 8;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    /// Two pixels per cell with the half block characters.
+    HalfBlock,
+    /// Eight pixels per cell with the braille patterns.
+    Braille,
+}
+
+impl Mode {
+    /// Pixels per cell horizontally and vertically.
+    fn cell_size(self) -> (usize, usize) {
+        match self {
+            Mode::HalfBlock => (1, 2),
+            Mode::Braille => (2, 4),
+        }
+    }
+}
+
+/// Restores the terminal however the frontend is left.
+struct Terminal {
+    out: Stdout,
+    enhanced: bool,
+}
+
+impl Terminal {
+    fn open() -> io::Result<Terminal> {
+        terminal::enable_raw_mode()?;
+
+        let mut out = io::stdout();
+        out.queue(EnterAlternateScreen)?.queue(Hide)?.queue(Clear(ClearType::All))?;
+
+        //terminals that can report key releases get exact press and release
+        let enhanced = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if enhanced {
+            out.queue(PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+        out.flush()?;
+
+        Ok(Terminal { out, enhanced })
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if self.enhanced {
+            let _ = self.out.queue(PopKeyboardEnhancementFlags);
+        }
+        let _ = self.out.queue(ResetColor).and_then(|out| out.queue(Show)).and_then(|out| out.queue(LeaveAlternateScreen));
+        let _ = self.out.flush();
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Plays a ROM in the terminal, escape or ctrl-c quits.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut path = None;
+    let mut mode = Mode::HalfBlock;
+    let mut foreground = Color::White;
+    let mut background = Color::Black;
+    let mut config = Config{ seed: super::time_seed(), ..Config::default() };
+    let mut keymap = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(super::usage);
+
+        match arg.as_str() {
+            "--braille" => mode = Mode::Braille,
+            "--fg" => foreground = parse_color(value()?)?,
+            "--bg" => background = parse_color(value()?)?,
+            "--seed" => config.seed = super::parse_number(value()?)? as u64,
+            "--speed" => config.instructions_per_second = super::parse_number(value()?)?,
+            "--quirks" => config.quirks = super::parse_quirks(value()?)?,
+            "--keymap" => keymap = Some(value()?.clone()),
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg.clone()),
+            _ => return Err(super::usage()),
+        }
+    }
+
+    let path = path.ok_or_else(super::usage)?;
+    let (rom, program) = super::load_program(&path)?;
+    let mut chip8 = Chip8::with_config(&rom, config).map_err(|err| format!("could not load {}: {}", path, err))?;
+    let keymap = super::load_keymap(keymap.as_deref(), &path, &rom)?;
+
+    let (cell_width, cell_height) = mode.cell_size();
+    let (columns, rows) = (SCREEN_WIDTH / cell_width, SCREEN_HEIGHT / cell_height);
+    let (width, height) = terminal::size().map_err(|err| err.to_string())?;
+    if (width as usize) < columns || (height as usize) < rows + 1 {
+        return Err(format!("the terminal needs at least {}x{} cells, it has {}x{}", columns, rows + 1, width, height));
+    }
+
+    let terminal = Terminal::open().map_err(|err| err.to_string())?;
+    let input = TermInput { keymap, enhanced: terminal.enhanced, held: [None; 16] };
+    let display = TermDisplay { terminal, mode, foreground, background, drawn: Vec::new(), size: (width, height) };
+    chip8.set_audio_sink(Box::new(Bell));
+
+    let mut driver = Driver::new(display, input, Realtime::new());
+    driver.run(&mut chip8).map_err(|err| match err {
+        DriverError::Emulation(err) => match program {
+            Some(ref program) => format!("{} ({})", err, program.locate(err.address())),
+            None => err.to_string(),
+        },
+        err => err.to_string(),
+    })
+}
+
+struct TermDisplay {
+    terminal: Terminal,
+    mode: Mode,
+    foreground: Color,
+    background: Color,
+    /// The cells on the terminal, empty to draw everything again.
+    drawn: Vec<char>,
+    size: (u16, u16),
+}
+
+impl DisplaySink for TermDisplay {
+    fn present(&mut self, gfx: &[u8], _frame: u64, _changed: bool) -> io::Result<()> {
+        //the old screen may be gone after a resize, draw every cell again
+        let size = terminal::size()?;
+        if size != self.size {
+            self.size = size;
+            self.drawn.clear();
+        }
+
+        draw(&mut self.terminal.out, gfx, self.mode, &mut self.drawn, self.foreground, self.background)
+    }
+}
+
+struct TermInput {
+    keymap: Keymap,
+    enhanced: bool,
+    /// The frame each key was last pressed in, while it is held.
+    held: [Option<u64>; 16],
+}
+
+impl InputSource for TermInput {
+    fn poll(&mut self, frame: u64) -> io::Result<Vec<Input>> {
+        let mut inputs = Vec::new();
+
+        while event::poll(Duration::from_secs(0))? {
+            if let Event::Key(key) = event::read()? {
+                let quit = key.code == KeyCode::Esc
+                    || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL));
+                if quit {
+                    inputs.push(Input::Quit);
+                    return Ok(inputs);
+                }
+
+                let hex = match key.code {
+                    KeyCode::Char(c) => self.keymap.key(c),
+                    _ => None,
+                };
+                if let Some(hex) = hex {
+                    let pressed = key.kind != KeyEventKind::Release;
+                    inputs.push(Input::key(hex, pressed));
+                    self.held[hex as usize] = if pressed { Some(frame) } else { None };
+                }
+            }
+        }
+
+        if !self.enhanced {
+            for (hex, since) in self.held.iter_mut().enumerate() {
+                if since.is_some_and(|since| since + KEY_HOLD_FRAMES <= frame) {
+                    inputs.push(Input::KeyUp(hex as u8));
+                    *since = None;
+                }
+            }
+        }
+
+        Ok(inputs)
+    }
+}
+
+/// Rings the terminal bell once per tone, terminals have no better sound.
+struct Bell;
+
+impl AudioSink for Bell {
+    fn start_tone(&mut self) {
+        let mut out = io::stdout();
+        let _ = out.write_all(b"\x07").and_then(|_| out.flush());
+    }
+
+    fn stop_tone(&mut self) {}
+}
+
+/// Writes the cells that differ from what is already on the terminal.
+fn draw(out: &mut Stdout, gfx: &[u8], mode: Mode, drawn: &mut Vec<char>, foreground: Color, background: Color) -> io::Result<()> {
+    let (cell_width, cell_height) = mode.cell_size();
+    let columns = SCREEN_WIDTH / cell_width;
+    let rows = SCREEN_HEIGHT / cell_height;
+
+    let first = drawn.is_empty();
+    if first {
+        out.queue(SetForegroundColor(foreground))?.queue(SetBackgroundColor(background))?.queue(Clear(ClearType::All))?;
+        out.queue(MoveTo(0, rows as u16))?.queue(Print("esc quits"))?;
+        drawn.resize(columns * rows, '\0');
+    }
+
+    let pixel = |x: usize, y: usize| gfx[y * SCREEN_WIDTH + x] != 0;
+
+    for row in 0..rows {
+        for column in 0..columns {
+            let (x, y) = (column * cell_width, row * cell_height);
+            let cell = match mode {
+                Mode::HalfBlock => match (pixel(x, y), pixel(x, y + 1)) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                },
+                Mode::Braille => {
+                    const DOTS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+                    let mut bits = 0;
+                    for (dx, column_dots) in DOTS.iter().enumerate() {
+                        for (dy, &dot) in column_dots.iter().enumerate() {
+                            if pixel(x + dx, y + dy) {
+                                bits |= dot;
+                            }
+                        }
+                    }
+                    std::char::from_u32(0x2800 + bits).unwrap_or(' ')
+                }
+            };
+
+            let index = row * columns + column;
+            if drawn[index] != cell {
+                drawn[index] = cell;
+                out.queue(MoveTo(column as u16, row as u16))?.queue(Print(cell))?;
+            }
+        }
+    }
+
+    out.flush()
+}
+
+/// Parses a color name, an ANSI color number or `#rrggbb`.
+fn parse_color(text: &str) -> Result<Color, String> {
+    let color = match text {
+        "black" => Color::Black,
+        "red" => Color::DarkRed,
+        "green" => Color::DarkGreen,
+        "yellow" => Color::DarkYellow,
+        "blue" => Color::DarkBlue,
+        "magenta" => Color::DarkMagenta,
+        "cyan" => Color::DarkCyan,
+        "grey" | "gray" => Color::Grey,
+        "white" => Color::White,
+        _ if text.starts_with('#') && text.len() == 7 => {
+            //get as the text may not be ASCII, the digit check keeps out signs
+            let channel = |range: std::ops::Range<usize>| {
+                text.get(range)
+                    .filter(|digits| digits.bytes().all(|byte| byte.is_ascii_hexdigit()))
+                    .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+            };
+            match (channel(1..3), channel(3..5), channel(5..7)) {
+                (Some(r), Some(g), Some(b)) => Color::Rgb { r, g, b },
+                _ => return Err(format!("invalid color: {}", text)),
+            }
+        }
+        _ => match text.parse() {
+            Ok(value) => Color::AnsiValue(value),
+            Err(_) => return Err(format!("invalid color: {}", text)),
+        },
+    };
+
+    Ok(color)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_colors() {
+        assert_eq!(parse_color("#1a2B3c"), Ok(Color::Rgb { r: 0x1A, g: 0x2B, b: 0x3C }));
+        assert!(parse_color("#1a2b3").is_err());
+        assert!(parse_color("#1a2b3g").is_err());
+        assert!(parse_color("#+1+2+3").is_err());
+        assert_eq!(parse_color("#a\u{e9}\u{e9}b"), Err("invalid color: #a\u{e9}\u{e9}b".to_string()));
+    }
+
+    #[test]
+    fn parses_named_and_ansi_colors() {
+        assert_eq!(parse_color("green"), Ok(Color::DarkGreen));
+        assert_eq!(parse_color("gray"), Ok(parse_color("grey").unwrap()));
+        assert_eq!(parse_color("208"), Ok(Color::AnsiValue(208)));
+        assert!(parse_color("256").is_err());
+        assert!(parse_color("purple").is_err());
+    }
+}
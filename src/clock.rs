@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+/// Rate of the delay and sound timers.
+pub const TIMER_HZ: u32 = 60;
+
+/// Wall clock time of one 60 Hz frame.
+pub fn frame_duration() -> Duration {
+    Duration::from_nanos(1_000_000_000 / TIMER_HZ as u64)
+}
+
+/// Schedules a CPU speed given in instructions per second onto 60 Hz frames.
+///
+/// Speeds that are not a multiple of 60 are spread over the frames, so
+/// that every second executes exactly `instructions_per_second` instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Clock {
+    instructions_per_second: u32,
+    remainder: u32,
+}
+
+impl Clock {
+    pub const DEFAULT_SPEED: u32 = 700;
+
+    pub fn new(instructions_per_second: u32) -> Clock {
+        Clock {
+            instructions_per_second,
+            remainder: 0,
+        }
+    }
+
+    /// Speeds above `u32::MAX` instructions per second are clamped.
+    pub fn from_instructions_per_frame(instructions_per_frame: u32) -> Clock {
+        Clock::new(instructions_per_frame.saturating_mul(TIMER_HZ))
+    }
+
+    pub fn instructions_per_second(&self) -> u32 {
+        self.instructions_per_second
+    }
+
+    pub fn set_instructions_per_second(&mut self, instructions_per_second: u32) {
+        self.instructions_per_second = instructions_per_second;
+        self.remainder = 0;
+    }
+
+    /// The fraction of an instruction carried into the next frame, in
+    /// 60ths. Save states keep it so that a restore stays exact.
+    pub(crate) fn remainder(&self) -> u32 {
+        self.remainder
+    }
+
+    pub(crate) fn set_remainder(&mut self, remainder: u32) {
+        self.remainder = remainder % TIMER_HZ;
+    }
+
+    /// The most instructions `next_frame` ever returns at this speed.
+    pub(crate) fn max_per_frame(&self) -> u32 {
+        self.instructions_per_second.div_ceil(TIMER_HZ)
+    }
+
+    /// Number of instructions to execute in the next frame.
+    pub fn next_frame(&mut self) -> u32 {
+        //in u64, the remainder may push the fastest speeds over u32::MAX
+        let total = self.instructions_per_second as u64 + self.remainder as u64;
+
+        self.remainder = (total % TIMER_HZ as u64) as u32;
+        (total / TIMER_HZ as u64) as u32
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Clock {
+        Clock::new(Clock::DEFAULT_SPEED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spreads_the_speed_over_the_frames() {
+        let mut clock = Clock::new(700);
+        let frames: Vec<u32> = (0..60 * 3).map(|_| clock.next_frame()).collect();
+
+        assert!(frames.iter().all(|&count| count == 11 || count == 12));
+        for n in 1..=frames.len() {
+            let executed: u32 = frames[..n].iter().sum();
+            assert_eq!(executed, 700 * n as u32 / 60, "after {} frames", n);
+        }
+    }
+
+    #[test]
+    fn multiples_of_sixty_run_the_same_count_every_frame() {
+        let mut clock = Clock::from_instructions_per_frame(15);
+        assert!((0..120).all(|_| clock.next_frame() == 15));
+    }
+
+    #[test]
+    fn fastest_speeds_do_not_overflow() {
+        let mut clock = Clock::from_instructions_per_frame(u32::MAX);
+        assert_eq!(clock.instructions_per_second(), u32::MAX);
+
+        let executed: u64 = (0..60).map(|_| clock.next_frame() as u64).sum();
+        assert_eq!(executed, u32::MAX as u64);
+    }
+}
@@ -0,0 +1,23 @@
+use clock::Clock;
+use quirks::Quirks;
+
+/// Everything besides the ROM and the keys pressed that decides how a
+/// program runs. Two machines with the same config, ROM and input behave
+/// identically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    pub quirks: Quirks,
+    pub instructions_per_second: u32,
+    /// Seed of the default random source used by CXNN.
+    pub seed: u64,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            quirks: Quirks::default(),
+            instructions_per_second: Clock::DEFAULT_SPEED,
+            seed: 0,
+        }
+    }
+}
@@ -0,0 +1,330 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use chip8::{Chip8, MEMORY_SIZE};
+use disasm::{self, Listing};
+use error::Chip8Error;
+
+/// A register of the machine as seen by a debugger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+    Pc,
+    /// Number of return addresses on the stack.
+    Sp,
+    Dt,
+    St,
+}
+
+impl Register {
+    /// V0 to VF, I, PC, SP, DT and ST, the order debuggers list them in.
+    pub fn all() -> Vec<Register> {
+        let mut registers: Vec<Register> = (0..16).map(Register::V).collect();
+        registers.extend_from_slice(&[Register::I, Register::Pc, Register::Sp, Register::Dt, Register::St]);
+        registers
+    }
+
+    /// Parses a register name like `v3`, `VA` or `pc`.
+    pub fn parse(name: &str) -> Option<Register> {
+        let name = name.to_lowercase();
+        let register = match name.as_str() {
+            "i" => Register::I,
+            "pc" => Register::Pc,
+            "sp" => Register::Sp,
+            "dt" => Register::Dt,
+            "st" => Register::St,
+            _ if name.len() == 2 && name.starts_with('v') => Register::V(u8::from_str_radix(&name[1..], 16).ok()?),
+            _ => return None,
+        };
+        Some(register)
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Register::V(x) => write!(f, "V{:X}", x),
+            Register::I => write!(f, "I"),
+            Register::Pc => write!(f, "PC"),
+            Register::Sp => write!(f, "SP"),
+            Register::Dt => write!(f, "DT"),
+            Register::St => write!(f, "ST"),
+        }
+    }
+}
+
+/// Why the debugger handed control back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// All requested instructions were executed.
+    Stepped,
+    /// The next instruction is at a breakpoint.
+    Breakpoint(u16),
+    /// The program is blocked on Fx0A, press a key to go on.
+    WaitingForKey,
+    /// The program jumped to itself and will never do anything else.
+    Halted,
+    /// The host asked to stop.
+    Interrupted,
+    Fault(Chip8Error),
+}
+
+/// Runs a machine instruction by instruction, with breakpoints.
+pub struct Debugger {
+    chip8: Chip8,
+    breakpoints: BTreeSet<u16>,
+}
+
+impl Debugger {
+    pub fn new(chip8: Chip8) -> Debugger {
+        Debugger { chip8, breakpoints: BTreeSet::new() }
+    }
+
+    pub fn chip8(&self) -> &Chip8 {
+        &self.chip8
+    }
+
+    pub fn chip8_mut(&mut self) -> &mut Chip8 {
+        &mut self.chip8
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<u16> {
+        &self.breakpoints
+    }
+
+    /// Returns false if there already was a breakpoint at `address`.
+    pub fn add_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.insert(address)
+    }
+
+    /// Returns false if there was no breakpoint at `address`.
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn register(&self, register: Register) -> u16 {
+        let chip8 = &self.chip8;
+        match register {
+            Register::V(x) => chip8.register(x) as u16,
+            Register::I => chip8.index(),
+            Register::Pc => chip8.program_counter(),
+            Register::Sp => chip8.stack_pointer as u16,
+            Register::Dt => chip8.delay_timer() as u16,
+            Register::St => chip8.sound_timer() as u16,
+        }
+    }
+
+    /// Checks that `value` fits into `register` without changing it, e.g.
+    /// fails for a PC outside of memory or more than 16 return addresses.
+    pub fn check_register(&self, register: Register, value: u16) -> Result<(), String> {
+        match register {
+            Register::V(_) | Register::Dt | Register::St if value > 0xFF => {
+                Err(format!("{} holds a byte, 0x{:X} does not fit", register, value))
+            }
+            Register::Pc if value as usize >= MEMORY_SIZE => Err(format!("0x{:X} is out of range for {}", value, register)),
+            Register::Sp if value as usize > self.chip8.stack.len() => {
+                Err(format!("0x{:X} is out of range for {}", value, register))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails, changing nothing, if the value does not fit, see
+    /// `check_register`.
+    pub fn set_register(&mut self, register: Register, value: u16) -> Result<(), String> {
+        self.check_register(register, value)?;
+
+        let chip8 = &mut self.chip8;
+        match register {
+            Register::V(x) => chip8.set_register(x, value as u8),
+            Register::I => chip8.set_index(value),
+            Register::Pc => {
+                chip8.set_program_counter(value);
+            }
+            //keeps the return addresses above SP, so lowering it can be undone
+            Register::Sp => chip8.stack_pointer = value as usize,
+            Register::Dt => chip8.set_delay_timer(value as u8),
+            Register::St => chip8.set_sound_timer(value as u8),
+        }
+
+        Ok(())
+    }
+
+    /// The return addresses, the innermost call last.
+    pub fn stack(&self) -> &[u16] {
+        self.chip8.stack()
+    }
+
+    /// Up to `length` bytes from `address`, fewer at the end of memory.
+    pub fn memory(&self, address: u16, length: usize) -> &[u8] {
+        self.chip8.read_memory(address, length)
+    }
+
+    /// Returns false, writing nothing, if the bytes do not fit into memory.
+    pub fn write_memory(&mut self, address: u16, bytes: &[u8]) -> bool {
+        self.chip8.write_memory(address, bytes)
+    }
+
+    /// Disassembles `count` words from `address` on.
+    pub fn disassemble(&self, address: u16, count: usize) -> Listing {
+        let start = address as usize;
+        disasm::disassemble_memory(self.chip8.memory(), start, start + 2 * count)
+    }
+
+    /// Executes up to `count` instructions. Breakpoints stop before the
+    /// instruction at them, except for the first one so that stepping off
+    /// a breakpoint works.
+    pub fn step(&mut self, count: u64) -> Stop {
+        for done in 0..count {
+            if done > 0 {
+                if let Some(stop) = self.breakpoint() {
+                    return stop;
+                }
+            }
+
+            match self.chip8.step_instruction() {
+                Ok(step) if step.self_jump => return Stop::Halted,
+                Ok(_) => (),
+                Err(err) => return Stop::Fault(err),
+            }
+        }
+
+        Stop::Stepped
+    }
+
+    /// Runs until a breakpoint, a fault, a key wait or a halt. `interrupt`
+    /// is asked once per frame whether the host wants to stop.
+    pub fn run<F: FnMut() -> bool>(&mut self, mut interrupt: F) -> Stop {
+        let mut first = true;
+        loop {
+            if !first {
+                if let Some(stop) = self.breakpoint() {
+                    return stop;
+                }
+            }
+            first = false;
+
+            let frame = self.chip8.frame_count();
+            match self.chip8.step_instruction() {
+                Ok(step) if step.waiting_for_key => return Stop::WaitingForKey,
+                Ok(step) if step.self_jump => return Stop::Halted,
+                Ok(_) => (),
+                Err(err) => return Stop::Fault(err),
+            }
+
+            if self.chip8.frame_count() != frame && interrupt() {
+                return Stop::Interrupted;
+            }
+        }
+    }
+
+    fn breakpoint(&self) -> Option<Stop> {
+        let pc = self.chip8.program_counter();
+        if self.breakpoints.contains(&pc) {
+            Some(Stop::Breakpoint(pc))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //LD V0, 1; ADD V0, 1; ADD V0, 1; halt
+    const ROM: &[u8] = &[0x60, 0x01, 0x70, 0x01, 0x70, 0x01, 0x12, 0x06];
+
+    fn debugger(rom: &[u8]) -> Debugger {
+        Debugger::new(Chip8::from_rom(rom).unwrap())
+    }
+
+    #[test]
+    fn steps_the_given_number_of_instructions() {
+        let mut debugger = debugger(ROM);
+
+        assert_eq!(debugger.step(2), Stop::Stepped);
+        assert_eq!((debugger.register(Register::V(0)), debugger.register(Register::Pc)), (2, 0x204));
+        assert_eq!(debugger.step(0), Stop::Stepped);
+        assert_eq!(debugger.register(Register::Pc), 0x204);
+        assert_eq!(debugger.step(10), Stop::Halted);
+        assert_eq!((debugger.register(Register::V(0)), debugger.register(Register::Pc)), (3, 0x206));
+    }
+
+    #[test]
+    fn breakpoints_stop_before_their_instruction() {
+        let mut debugger = debugger(ROM);
+        assert!(debugger.add_breakpoint(0x204));
+        assert!(!debugger.add_breakpoint(0x204));
+
+        assert_eq!(debugger.step(10), Stop::Breakpoint(0x204));
+        assert_eq!(debugger.register(Register::V(0)), 2);
+
+        //the first instruction runs even at a breakpoint, so stepping goes on
+        assert_eq!(debugger.step(1), Stop::Stepped);
+        assert_eq!(debugger.register(Register::Pc), 0x206);
+
+        debugger.chip8_mut().set_program_counter(0x200);
+        assert_eq!(debugger.run(|| false), Stop::Breakpoint(0x204));
+        assert_eq!(debugger.run(|| false), Stop::Halted);
+
+        assert!(debugger.remove_breakpoint(0x204));
+        assert!(!debugger.remove_breakpoint(0x204));
+        debugger.add_breakpoint(0x202);
+        debugger.clear_breakpoints();
+        assert!(debugger.breakpoints().is_empty());
+    }
+
+    #[test]
+    fn run_reports_why_it_stopped() {
+        assert_eq!(debugger(&[0xF0, 0x0A]).run(|| false), Stop::WaitingForKey);
+        assert_eq!(
+            debugger(&[0x00, 0x00]).run(|| false),
+            Stop::Fault(Chip8Error::UnknownOpcode { opcode: 0x0000, address: 0x200 })
+        );
+
+        //ADD V0, 1; JP 0x200 never stops on its own
+        let mut debugger = debugger(&[0x70, 0x01, 0x12, 0x00]);
+        let mut asked = 0;
+        let stop = debugger.run(|| {
+            asked += 1;
+            asked == 3
+        });
+        assert_eq!((stop, debugger.chip8().frame_count()), (Stop::Interrupted, 3));
+    }
+
+    #[test]
+    fn set_register_rejects_values_that_do_not_fit() {
+        let mut debugger = debugger(ROM);
+
+        assert!(debugger.set_register(Register::V(3), 0x100).is_err());
+        assert!(debugger.set_register(Register::Dt, 0x100).is_err());
+        assert!(debugger.set_register(Register::St, 0x1FF).is_err());
+        assert!(debugger.set_register(Register::Pc, 0x1000).is_err());
+        assert!(debugger.set_register(Register::Sp, 17).is_err());
+        assert_eq!(debugger.register(Register::Pc), 0x200);
+
+        debugger.set_register(Register::V(3), 0xFF).unwrap();
+        debugger.set_register(Register::I, 0xFFFF).unwrap();
+        debugger.set_register(Register::Pc, 0xFFE).unwrap();
+        debugger.set_register(Register::Sp, 16).unwrap();
+        assert_eq!(debugger.register(Register::V(3)), 0xFF);
+        assert_eq!(debugger.register(Register::I), 0xFFFF);
+        assert_eq!(debugger.register(Register::Pc), 0xFFE);
+        assert_eq!(debugger.register(Register::Sp), 16);
+    }
+
+    #[test]
+    fn parses_register_names() {
+        assert_eq!(Register::parse("vA"), Some(Register::V(0xA)));
+        assert_eq!(Register::parse("PC"), Some(Register::Pc));
+        assert_eq!(Register::parse("vg"), None);
+        assert_eq!(Register::parse("v10"), None);
+        assert_eq!(Register::all().len(), 21);
+    }
+}
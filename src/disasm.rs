@@ -0,0 +1,322 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fmt::Write;
+
+use chip8::MEMORY_SIZE;
+use error::LoadError;
+use instruction::{decode, Instruction};
+
+/// Number of bytes shown per line in regions that are plain data.
+const DATA_PER_LINE: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+    Code(Instruction),
+    /// A single row of a sprite referenced by `LD I, addr`.
+    Sprite(u8),
+    /// Bytes that are never executed nor loaded into I.
+    Data(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub address: u16,
+    pub label: Option<String>,
+    pub bytes: Vec<u8>,
+    pub item: Item,
+}
+
+/// A disassembled ROM or memory range.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Listing {
+    pub lines: Vec<Line>,
+    labels: BTreeMap<u16, String>,
+}
+
+impl Listing {
+    pub fn label(&self, address: u16) -> Option<&str> {
+        self.labels.get(&address).map(|label| label.as_str())
+    }
+
+    /// Formats an instruction, naming its address operand by label if possible.
+    pub fn mnemonic(&self, instruction: &Instruction) -> String {
+        let mut text = String::new();
+        let _ = instruction.write_mnemonic(&mut text, |addr| match self.labels.get(&addr) {
+            Some(label) => label.clone(),
+            None => format!("0x{:03X}", addr),
+        });
+        text
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut previous: Option<&Item> = None;
+
+        for line in &self.lines {
+            match (previous, &line.item) {
+                (Some(&Item::Sprite(_)), &Item::Sprite(_)) | (Some(&Item::Data(_)), &Item::Data(_)) => (),
+                (_, &Item::Sprite(_)) => writeln!(f, "; sprite")?,
+                (_, &Item::Data(_)) => writeln!(f, "; data")?,
+                _ => (),
+            }
+            previous = Some(&line.item);
+
+            if let Some(ref label) = line.label {
+                writeln!(f, "{}:", label)?;
+            }
+
+            let raw: String = line.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            write!(f, "    0x{:03X}  {:<8}  ", line.address, raw)?;
+
+            match line.item {
+                Item::Code(ref instruction) => writeln!(f, "{}", self.mnemonic(instruction))?,
+                Item::Sprite(row) => {
+                    let pixels: String = (0..8)
+                        .map(|bit| if row & (0x80 >> bit) != 0 { '#' } else { '.' })
+                        .collect();
+                    writeln!(f, "{:<20}; {}", format!("DB 0x{:02X}", row), pixels)?
+                }
+                Item::Data(ref bytes) => {
+                    let mut text = String::from("DB ");
+                    for (i, byte) in bytes.iter().enumerate() {
+                        if i > 0 {
+                            text.push_str(", ");
+                        }
+                        write!(text, "0x{:02X}", byte)?;
+                    }
+                    writeln!(f, "{}", text)?
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Disassembles a ROM loaded at `origin` by following the control flow
+/// from its first instruction.
+///
+/// Everything the flow does not reach is listed as data, bytes loaded into
+/// I are shown as sprite rows. `JP V0` tables can only be followed for
+/// V0 = 0, so code behind them may show up as data.
+///
+/// Fails if the ROM does not fit into memory at `origin`.
+pub fn disassemble(rom: &[u8], origin: u16) -> Result<Listing, LoadError> {
+    if origin as usize + rom.len() > MEMORY_SIZE {
+        return Err(LoadError::RomTooLarge { size: rom.len(), max: MEMORY_SIZE.saturating_sub(origin as usize) });
+    }
+
+    let mut is_code = vec![false; rom.len()];
+    let mut covered = vec![false; rom.len()];
+    let mut code_targets = BTreeSet::new();
+    let mut data_targets = BTreeSet::new();
+
+    let mut pending = vec![origin];
+    while let Some(address) = pending.pop() {
+        let offset = match offset_of(address, origin, rom.len()) {
+            Some(offset) if offset + 1 < rom.len() => offset,
+            _ => continue,
+        };
+        if is_code[offset] || covered[offset] || covered[offset + 1] {
+            continue;
+        }
+
+        let instruction = decode(word_at(rom, offset));
+        match instruction {
+            Instruction::Sys { .. } | Instruction::Unknown { .. } => continue,
+            _ => (),
+        }
+
+        is_code[offset] = true;
+        covered[offset] = true;
+        covered[offset + 1] = true;
+
+        let next = address + 2;
+        match instruction {
+            Instruction::Jmp { addr } => {
+                code_targets.insert(addr);
+                pending.push(addr);
+            }
+            Instruction::Call { addr } => {
+                code_targets.insert(addr);
+                pending.push(addr);
+                pending.push(next);
+            }
+            Instruction::JmpV0 { addr } => {
+                code_targets.insert(addr);
+                pending.push(addr);
+            }
+            Instruction::Ret => (),
+            Instruction::LdI { addr } => {
+                data_targets.insert(addr);
+                pending.push(next);
+            }
+            _ if instruction.is_skip() => {
+                pending.push(next);
+                pending.push(next + 2);
+            }
+            _ => pending.push(next),
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    let mut in_sprite = false;
+    while offset < rom.len() {
+        let address = origin + offset as u16;
+
+        if is_code[offset] {
+            lines.push(code_line(rom, offset, address));
+            in_sprite = false;
+            offset += 2;
+            continue;
+        }
+
+        if data_targets.contains(&address) {
+            in_sprite = true;
+        }
+
+        if in_sprite {
+            lines.push(Line {
+                address,
+                label: None,
+                bytes: vec![rom[offset]],
+                item: Item::Sprite(rom[offset]),
+            });
+            offset += 1;
+            continue;
+        }
+
+        //plain data runs end at code and anything that may get a label
+        let mut end = offset + 1;
+        while end < rom.len() && end - offset < DATA_PER_LINE && !is_code[end] {
+            let next = origin + end as u16;
+            if code_targets.contains(&next) || data_targets.contains(&next) {
+                break;
+            }
+            end += 1;
+        }
+        let bytes = rom[offset..end].to_vec();
+        lines.push(Line {
+            address,
+            label: None,
+            bytes: bytes.clone(),
+            item: Item::Data(bytes),
+        });
+        offset = end;
+    }
+
+    let mut labels = BTreeMap::new();
+    for line in &lines {
+        let code = matches!(line.item, Item::Code(_));
+        if code_targets.contains(&line.address) || (code && line.address == origin) {
+            labels.insert(line.address, format!("L{:03X}", line.address));
+        } else if data_targets.contains(&line.address) {
+            labels.insert(line.address, format!("S{:03X}", line.address));
+        }
+    }
+
+    Ok(with_labels(lines, labels))
+}
+
+/// Disassembles `memory[start..end]` word by word without any flow
+/// analysis, e.g. to show the code around the program counter.
+pub fn disassemble_memory(memory: &[u8], start: usize, end: usize) -> Listing {
+    let end = end.min(memory.len());
+    let mut lines = Vec::new();
+
+    let mut offset = start;
+    while offset + 1 < end {
+        lines.push(code_line(memory, offset, offset as u16));
+        offset += 2;
+    }
+    if offset < end {
+        lines.push(Line {
+            address: offset as u16,
+            label: None,
+            bytes: vec![memory[offset]],
+            item: Item::Data(vec![memory[offset]]),
+        });
+    }
+
+    let starts: BTreeSet<u16> = lines.iter().map(|line| line.address).collect();
+    let mut labels = BTreeMap::new();
+    for line in &lines {
+        if let Item::Code(ref instruction) = line.item {
+            match *instruction {
+                Instruction::Jmp { addr } | Instruction::Call { addr } | Instruction::JmpV0 { addr }
+                    if starts.contains(&addr) => {
+                    labels.insert(addr, format!("L{:03X}", addr));
+                }
+                _ => (),
+            }
+        }
+    }
+
+    with_labels(lines, labels)
+}
+
+fn with_labels(mut lines: Vec<Line>, labels: BTreeMap<u16, String>) -> Listing {
+    for line in &mut lines {
+        line.label = labels.get(&line.address).cloned();
+    }
+
+    Listing { lines, labels }
+}
+
+fn code_line(bytes: &[u8], offset: usize, address: u16) -> Line {
+    Line {
+        address,
+        label: None,
+        bytes: bytes[offset..offset + 2].to_vec(),
+        item: Item::Code(decode(word_at(bytes, offset))),
+    }
+}
+
+fn offset_of(address: u16, origin: u16, len: usize) -> Option<usize> {
+    if address < origin || (address - origin) as usize >= len {
+        None
+    } else {
+        Some((address - origin) as usize)
+    }
+}
+
+fn word_at(bytes: &[u8], offset: usize) -> u16 {
+    (bytes[offset] as u16) << 8 | bytes[offset + 1] as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_the_flow_and_finds_sprites() {
+        //LD I, 0x206; DRW V0, V0, 1; JP 0x204; sprite row
+        let rom = [0xA2, 0x06, 0xD0, 0x01, 0x12, 0x04, 0xF0];
+        let listing = disassemble(&rom, 0x200).unwrap();
+
+        let items: Vec<&Item> = listing.lines.iter().map(|line| &line.item).collect();
+        assert_eq!(items, [
+            &Item::Code(Instruction::LdI { addr: 0x206 }),
+            &Item::Code(Instruction::Drw { x: 0, y: 0, n: 1 }),
+            &Item::Code(Instruction::Jmp { addr: 0x204 }),
+            &Item::Sprite(0xF0),
+        ]);
+        assert_eq!(listing.label(0x204), Some("L204"));
+        assert_eq!(listing.label(0x206), Some("S206"));
+    }
+
+    #[test]
+    fn unreachable_bytes_are_data() {
+        let listing = disassemble(&[0x12, 0x00, 0xFF, 0xFF], 0x200).unwrap();
+        assert_eq!(listing.lines[1].item, Item::Data(vec![0xFF, 0xFF]));
+    }
+
+    #[test]
+    fn rejects_roms_past_the_end_of_memory() {
+        assert!(disassemble(&[0x00, 0xE0], 0xFFE).is_ok());
+        assert!(disassemble(&[0x00, 0xE0], 0xFFF).is_err());
+        assert!(disassemble(&[0x00, 0xE0], 0xFFFF).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Errors that can occur while loading a ROM into memory.
+#[derive(Debug)]
+pub enum LoadError {
+    /// The ROM could not be read from its source.
+    Io(io::Error),
+    /// The ROM does not fit into memory at its load address.
+    RomTooLarge { size: usize, max: usize },
+    /// The ROM contains no bytes.
+    EmptyRom,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::Io(ref err) => write!(f, "could not read rom: {}", err),
+            LoadError::RomTooLarge { size, max } => {
+                write!(f, "rom is {} bytes, at most {} bytes fit into memory", size, max)
+            }
+            LoadError::EmptyRom => write!(f, "rom is empty"),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            LoadError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> LoadError {
+        LoadError::Io(err)
+    }
+}
+
+/// Faults raised by the interpreter while executing a program.
+///
+/// Every variant carries the address of the instruction that caused it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip8Error {
+    /// The opcode does not name any known instruction.
+    UnknownOpcode { opcode: u16, address: u16 },
+    /// A subroutine call was made with all 16 stack slots in use.
+    StackOverflow { address: u16 },
+    /// A return was made with an empty stack.
+    StackUnderflow { address: u16 },
+    /// The instruction tried to access memory past 0xFFF.
+    MemoryOutOfBounds { address: u16, access: usize },
+}
+
+impl Chip8Error {
+    /// Address of the instruction that failed.
+    pub fn address(&self) -> u16 {
+        match *self {
+            Chip8Error::UnknownOpcode { address, .. }
+            | Chip8Error::StackOverflow { address }
+            | Chip8Error::StackUnderflow { address }
+            | Chip8Error::MemoryOutOfBounds { address, .. } => address,
+        }
+    }
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Chip8Error::UnknownOpcode { opcode, address } => {
+                write!(f, "unknown opcode 0x{:04X} at 0x{:03X}", opcode, address)
+            }
+            Chip8Error::StackOverflow { address } => {
+                write!(f, "stack overflow at 0x{:03X}", address)
+            }
+            Chip8Error::StackUnderflow { address } => {
+                write!(f, "stack underflow at 0x{:03X}", address)
+            }
+            Chip8Error::MemoryOutOfBounds { address, access } => {
+                write!(f, "out of bounds memory access to 0x{:X} at 0x{:03X}", access, address)
+            }
+        }
+    }
+}
+
+impl Error for Chip8Error {}
+
+/// Errors that can occur while restoring a save state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateError {
+    /// The data does not start with the save state magic bytes.
+    NotAState,
+    /// The state was written by a newer, unknown format version.
+    UnsupportedVersion(u16),
+    /// The data ends before the state is complete.
+    Truncated,
+    /// The stored checksum does not match the contents.
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// A field holds a value no machine can be in, e.g. a PC past 0xFFF.
+    InvalidField(&'static str),
+    /// The state is longer than its version's fields.
+    TrailingBytes(usize),
+    /// Only one of the state and the machine has a random source that can
+    /// be saved, so the random numbers can not be restored.
+    RandomSourceMismatch,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::NotAState => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::ChecksumMismatch { expected, actual } => {
+                write!(f, "save state checksum is 0x{:08X}, expected 0x{:08X}", actual, expected)
+            }
+            StateError::InvalidField(field) => write!(f, "save state has an invalid {}", field),
+            StateError::TrailingBytes(count) => write!(f, "save state has {} unexpected trailing bytes", count),
+            StateError::RandomSourceMismatch => {
+                write!(f, "save state random numbers can not be restored into this random source")
+            }
+        }
+    }
+}
+
+impl Error for StateError {}
+
+/// Errors that can occur while reading or replaying a movie.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieError {
+    /// The movie file is malformed at the given 1 based line.
+    Parse { line: usize, message: String },
+    /// The movie was recorded with a different ROM.
+    RomMismatch { expected: u32, actual: u32 },
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MovieError::Parse { line, ref message } => write!(f, "movie line {}: {}", line, message),
+            MovieError::RomMismatch { expected, actual } => {
+                write!(f, "movie was recorded for rom {:08x}, this rom is {:08x}", expected, actual)
+            }
+        }
+    }
+}
+
+impl Error for MovieError {}
+
+/// Errors that end a frontend's run loop.
+#[derive(Debug)]
+pub enum DriverError {
+    /// The program did something the machine cannot do.
+    Emulation(Chip8Error),
+    /// The display or input failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for DriverError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DriverError::Emulation(ref err) => err.fmt(f),
+            DriverError::Io(ref err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for DriverError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            DriverError::Emulation(ref err) => Some(err),
+            DriverError::Io(ref err) => Some(err),
+        }
+    }
+}
+
+impl From<Chip8Error> for DriverError {
+    fn from(err: Chip8Error) -> DriverError {
+        DriverError::Emulation(err)
+    }
+}
+
+impl From<io::Error> for DriverError {
+    fn from(err: io::Error) -> DriverError {
+        DriverError::Io(err)
+    }
+}
@@ -0,0 +1,298 @@
+use std::io;
+use std::thread;
+use std::time::Instant;
+
+use chip8::Chip8;
+use clock;
+use error::DriverError;
+use movie::{KeyEvent, Player};
+use rewind::Rewind;
+
+/// What a frontend asks the driver to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    KeyDown(u8),
+    KeyUp(u8),
+    /// Starts or stops stepping backwards, needs `Driver::with_rewind`.
+    Rewind(bool),
+    Quit,
+}
+
+impl Input {
+    pub fn key(key: u8, pressed: bool) -> Input {
+        if pressed {
+            Input::KeyDown(key)
+        } else {
+            Input::KeyUp(key)
+        }
+    }
+}
+
+/// Shows the display.
+pub trait DisplaySink {
+    /// Called before the first frame and after every frame, `frame` is the
+    /// number of frames run so far. Sinks that only care about changes can
+    /// skip the call when `changed` is false.
+    fn present(&mut self, gfx: &[u8], frame: u64, changed: bool) -> io::Result<()>;
+}
+
+/// Reports the keys and commands since the last poll, called once before
+/// every frame.
+pub trait InputSource {
+    fn poll(&mut self, frame: u64) -> io::Result<Vec<Input>>;
+}
+
+/// Decides when the next frame may run.
+pub trait TimingSource {
+    fn wait_for_frame(&mut self);
+}
+
+/// A display that shows nothing, e.g. for replays that only check the end.
+impl DisplaySink for () {
+    fn present(&mut self, _gfx: &[u8], _frame: u64, _changed: bool) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// No input at all.
+impl InputSource for () {
+    fn poll(&mut self, _frame: u64) -> io::Result<Vec<Input>> {
+        Ok(Vec::new())
+    }
+}
+
+/// Runs frames at 60 Hz, skipping ahead if the host fell behind instead of
+/// running a burst of frames to catch up.
+pub struct Realtime {
+    next_frame: Instant,
+}
+
+impl Realtime {
+    pub fn new() -> Realtime {
+        Realtime { next_frame: Instant::now() }
+    }
+}
+
+impl Default for Realtime {
+    fn default() -> Realtime {
+        Realtime::new()
+    }
+}
+
+impl TimingSource for Realtime {
+    fn wait_for_frame(&mut self) {
+        self.next_frame += clock::frame_duration();
+        let now = Instant::now();
+        if self.next_frame > now {
+            thread::sleep(self.next_frame - now);
+        } else {
+            self.next_frame = now;
+        }
+    }
+}
+
+/// Runs frames as fast as the host can, for headless runs and tests.
+pub struct Unthrottled;
+
+impl TimingSource for Unthrottled {
+    fn wait_for_frame(&mut self) {}
+}
+
+/// Key events played back at the frames they are listed for, e.g. a test
+/// script or the keys of a movie.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyScript {
+    events: Vec<KeyEvent>,
+    next: usize,
+}
+
+impl KeyScript {
+    pub fn new(mut events: Vec<KeyEvent>) -> KeyScript {
+        //stable, so events of the same frame keep their order
+        events.sort_by_key(|event| event.frame);
+        KeyScript { events, next: 0 }
+    }
+
+    /// The events up to and including `frame` that were not taken yet.
+    pub fn due(&mut self, frame: u64) -> &[KeyEvent] {
+        let start = self.next;
+        while self.events.get(self.next).is_some_and(|event| event.frame <= frame) {
+            self.next += 1;
+        }
+        &self.events[start..self.next]
+    }
+}
+
+impl InputSource for KeyScript {
+    fn poll(&mut self, frame: u64) -> io::Result<Vec<Input>> {
+        Ok(self.due(frame).iter().map(|event| event.input()).collect())
+    }
+}
+
+impl InputSource for Player {
+    fn poll(&mut self, frame: u64) -> io::Result<Vec<Input>> {
+        Ok(self.due(frame).iter().map(|event| event.input()).collect())
+    }
+}
+
+impl KeyEvent {
+    pub fn input(&self) -> Input {
+        Input::key(self.key, self.pressed)
+    }
+}
+
+/// The run loop every frontend shares: poll input, run a frame, present
+/// the display, wait for the next frame.
+pub struct Driver<D, I, T> {
+    pub display: D,
+    pub input: I,
+    pub timing: T,
+    rewind: Option<Rewind>,
+    frame_limit: Option<u64>,
+}
+
+impl<D: DisplaySink, I: InputSource, T: TimingSource> Driver<D, I, T> {
+    pub fn new(display: D, input: I, timing: T) -> Driver<D, I, T> {
+        Driver { display, input, timing, rewind: None, frame_limit: None }
+    }
+
+    /// Records every frame, so `Input::Rewind` can step back through them.
+    pub fn with_rewind(mut self, rewind: Rewind) -> Driver<D, I, T> {
+        self.rewind = Some(rewind);
+        self
+    }
+
+    /// Stops once the machine has run this many frames in total.
+    pub fn with_frame_limit(mut self, frames: u64) -> Driver<D, I, T> {
+        self.frame_limit = Some(frames);
+        self
+    }
+
+    /// Runs until the input asks to quit, the frame limit is reached or the
+    /// emulation fails. On failure the machine is left at the faulting
+    /// instruction.
+    pub fn run(&mut self, chip8: &mut Chip8) -> Result<(), DriverError> {
+        let mut rewinding = false;
+
+        self.display.present(chip8.get_gfx(), chip8.frame_count(), true)?;
+
+        loop {
+            if self.frame_limit.is_some_and(|limit| chip8.frame_count() >= limit) {
+                return Ok(());
+            }
+
+            for input in self.input.poll(chip8.frame_count())? {
+                match input {
+                    Input::KeyDown(key) => chip8.key_down(key),
+                    Input::KeyUp(key) => chip8.key_up(key),
+                    Input::Rewind(on) => rewinding = on,
+                    Input::Quit => return Ok(()),
+                }
+            }
+
+            let changed = match self.rewind {
+                Some(ref mut rewind) if rewinding => match rewind.step_back(chip8) {
+                    Ok(_) => true,
+                    //the random source was swapped, the history is of no use anymore
+                    Err(_) => {
+                        rewind.clear();
+                        false
+                    }
+                },
+                _ => {
+                    let frame = chip8.run_frame()?;
+                    if let Some(ref mut rewind) = self.rewind {
+                        rewind.record(chip8);
+                    }
+                    frame.screen_changed
+                }
+            };
+
+            self.display.present(chip8.get_gfx(), chip8.frame_count(), changed)?;
+            self.timing.wait_for_frame();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records what it was asked to show.
+    #[derive(Default)]
+    struct Screen {
+        /// Frame, changed and whether the top left pixel was lit.
+        presented: Vec<(u64, bool, bool)>,
+    }
+
+    impl DisplaySink for Screen {
+        fn present(&mut self, gfx: &[u8], frame: u64, changed: bool) -> io::Result<()> {
+            self.presented.push((frame, changed, gfx[0] != 0));
+            Ok(())
+        }
+    }
+
+    /// Hands out inputs at the frames they are listed for.
+    struct Script {
+        inputs: Vec<(u64, Input)>,
+        polled: Vec<u64>,
+    }
+
+    impl InputSource for Script {
+        fn poll(&mut self, frame: u64) -> io::Result<Vec<Input>> {
+            self.polled.push(frame);
+            Ok(self.inputs.iter().filter(|&&(at, _)| at == frame).map(|&(_, input)| input).collect())
+        }
+    }
+
+    #[derive(Default)]
+    struct Ticks(u64);
+
+    impl TimingSource for Ticks {
+        fn wait_for_frame(&mut self) {
+            self.0 += 1;
+        }
+    }
+
+    //LD V1, K; LD F, V1; DRW V0, V0, 5; halt
+    const ROM: &[u8] = &[0xF1, 0x0A, 0xF1, 0x29, 0xD0, 0x05, 0x12, 0x06];
+
+    #[test]
+    fn driver_delivers_keys_and_presents_every_frame() {
+        let mut chip8 = Chip8::from_rom(ROM).unwrap();
+        let input = Script { inputs: vec![(2, Input::KeyDown(0x8)), (3, Input::KeyUp(0x8))], polled: Vec::new() };
+        let mut driver = Driver::new(Screen::default(), input, Ticks::default()).with_frame_limit(5);
+
+        driver.run(&mut chip8).unwrap();
+
+        assert_eq!(chip8.frame_count(), 5);
+        assert_eq!(chip8.register(1), 0x8);
+        assert_eq!(driver.input.polled, [0, 1, 2, 3, 4]);
+        assert_eq!(driver.timing.0, 5);
+        assert_eq!(
+            driver.display.presented,
+            [(0, true, false), (1, false, false), (2, false, false), (3, true, true), (4, false, true), (5, false, true)]
+        );
+    }
+
+    #[test]
+    fn driver_stops_on_quit() {
+        let mut chip8 = Chip8::from_rom(ROM).unwrap();
+        let input = Script { inputs: vec![(3, Input::Quit)], polled: Vec::new() };
+        let mut driver = Driver::new((), input, Unthrottled);
+
+        driver.run(&mut chip8).unwrap();
+        assert_eq!(chip8.frame_count(), 3);
+    }
+
+    #[test]
+    fn key_script_hands_out_each_event_once() {
+        let event = |frame, key, pressed| KeyEvent { frame, key, pressed };
+        let mut script = KeyScript::new(vec![event(4, 0x1, false), event(2, 0x1, true), event(2, 0xF, true)]);
+
+        assert_eq!(script.poll(1).unwrap(), []);
+        assert_eq!(script.poll(3).unwrap(), [Input::KeyDown(0x1), Input::KeyDown(0xF)]);
+        assert_eq!(script.poll(3).unwrap(), []);
+        assert_eq!(script.poll(10).unwrap(), [Input::KeyUp(0x1)]);
+    }
+}
@@ -0,0 +1,524 @@
+use std::convert::TryFrom;
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+use chip8::MEMORY_SIZE;
+use debugger::{Debugger, Register, Stop};
+use error::Chip8Error;
+
+/// Largest packet gdb may send us, announced in `qSupported`.
+const PACKET_SIZE: usize = 0x1000;
+
+/// Most bytes an `m` packet reads, two hex digits each must fit a packet.
+const MAX_READ: usize = PACKET_SIZE / 2;
+
+const INTERRUPT: u8 = 0x03;
+
+/// Signals reported in stop replies.
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+const MONITOR_HELP: &str = "\
+key <hex> [down|up]  press or release a key of the keypad
+";
+
+/// How a gdb session ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionEnd {
+    /// gdb detached, the machine is left as it is for the next session.
+    Detached,
+    /// gdb asked to kill the program.
+    Killed,
+    /// The connection was closed without either.
+    Disconnected,
+}
+
+/// The register layout gdb is told about: V0 to VF, I, PC, SP, DT and ST
+/// in the order of the `g` packet. 16 bit registers are sent big endian,
+/// like the machine stores words in memory.
+pub fn target_xml() -> String {
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n");
+    xml.push_str("<target version=\"1.0\">\n  <feature name=\"org.chip8.core\">\n");
+    for register in Register::all() {
+        let kind = match register {
+            Register::I => "data_ptr",
+            Register::Pc => "code_ptr",
+            _ => "uint8",
+        };
+        let _ = writeln!(
+            xml,
+            "    <reg name=\"{}\" bitsize=\"{}\" type=\"{}\"/>",
+            register.to_string().to_lowercase(),
+            width(register) * 8,
+            kind
+        );
+    }
+    xml.push_str("  </feature>\n</target>\n");
+    xml
+}
+
+/// A GDB remote serial protocol server around a debugger, one session
+/// per connection.
+///
+/// Supports reading and writing registers and memory, single steps,
+/// continue with ctrl-c, software breakpoints (`Z0`) and the target
+/// description. `monitor key <hex>` presses keys, so a program waiting on
+/// Fx0A can go on.
+pub struct GdbServer {
+    debugger: Debugger,
+    no_ack: bool,
+    /// The last packet sent, to send again when gdb asks for it.
+    last: Vec<u8>,
+}
+
+enum Incoming {
+    Packet(String),
+    Interrupt,
+    Ack,
+    Nack,
+    /// A packet with a wrong checksum.
+    Corrupt,
+    Closed,
+}
+
+impl GdbServer {
+    pub fn new(debugger: Debugger) -> GdbServer {
+        GdbServer { debugger, no_ack: false, last: Vec::new() }
+    }
+
+    pub fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    pub fn debugger_mut(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    /// Answers gdb's packets until it detaches, kills or disconnects.
+    pub fn serve(&mut self, stream: &mut TcpStream) -> io::Result<SessionEnd> {
+        self.no_ack = false;
+
+        loop {
+            let packet = match read_packet(stream)? {
+                Incoming::Packet(packet) => packet,
+                Incoming::Ack => continue,
+                Incoming::Nack => {
+                    stream.write_all(&self.last)?;
+                    continue;
+                }
+                Incoming::Corrupt => {
+                    stream.write_all(b"-")?;
+                    continue;
+                }
+                //the program is stopped already, just say so
+                Incoming::Interrupt => {
+                    self.send(stream, &format!("S{:02x}", SIGINT))?;
+                    continue;
+                }
+                Incoming::Closed => return Ok(SessionEnd::Disconnected),
+            };
+
+            if !self.no_ack {
+                stream.write_all(b"+")?;
+            }
+
+            match packet.as_str() {
+                "k" => return Ok(SessionEnd::Killed),
+                _ if packet.starts_with('D') => {
+                    self.send(stream, "OK")?;
+                    return Ok(SessionEnd::Detached);
+                }
+                "QStartNoAckMode" => {
+                    self.send(stream, "OK")?;
+                    self.no_ack = true;
+                }
+                _ => {
+                    let reply = self.handle(&packet, stream)?;
+                    self.send(stream, &reply)?;
+                }
+            }
+        }
+    }
+
+    /// The reply to a packet, empty for packets we do not support.
+    fn handle(&mut self, packet: &str, stream: &mut TcpStream) -> io::Result<String> {
+        const ERROR: &str = "E01";
+
+        let reply = match packet {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => Register::all().into_iter().map(|register| self.encode_register(register)).collect(),
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ if packet.starts_with("qSupported") => {
+                format!("PacketSize={:x};qXfer:features:read+;swbreak+;QStartNoAckMode+", PACKET_SIZE)
+            }
+            _ if packet.starts_with("qXfer:features:read:target.xml:") => {
+                let range = &packet["qXfer:features:read:target.xml:".len()..];
+                match parse_pair(range, ',') {
+                    Some((offset, length)) => {
+                        let xml = target_xml();
+                        let start = (offset as usize).min(xml.len());
+                        let end = start.saturating_add(length as usize).min(xml.len());
+                        let more = if end < xml.len() { 'm' } else { 'l' };
+                        format!("{}{}", more, &xml[start..end])
+                    }
+                    None => ERROR.to_string(),
+                }
+            }
+            _ if packet.starts_with("qRcmd,") => match decode_hex(&packet["qRcmd,".len()..]) {
+                Some(command) => match self.monitor(&String::from_utf8_lossy(&command)) {
+                    ref output if output.is_empty() => "OK".to_string(),
+                    output => encode_hex(output.as_bytes()),
+                },
+                None => ERROR.to_string(),
+            },
+            _ if packet.starts_with('H') => "OK".to_string(),
+            _ if packet.starts_with('s') || packet.starts_with('c') => {
+                if packet.len() > 1 {
+                    let resumed = parse_hex(&packet[1..])
+                        .and_then(|address| self.register_value(Register::Pc, address))
+                        .and_then(|address| self.debugger.set_register(Register::Pc, address).ok());
+                    if resumed.is_none() {
+                        return Ok(ERROR.to_string());
+                    }
+                }
+
+                if packet.starts_with('s') {
+                    let stop = self.debugger.step(1);
+                    self.stop_reply(stop)
+                } else {
+                    self.resume(stream)?
+                }
+            }
+            _ if packet.starts_with('G') => {
+                //all or nothing, a bad value must not leave half the registers written
+                let mut values = &packet[1..];
+                let mut writes = Vec::new();
+                for register in Register::all() {
+                    let digits = width(register) * 2;
+                    let value = values.get(..digits).and_then(parse_hex).and_then(|value| self.register_value(register, value));
+                    let value = match value {
+                        Some(value) => value,
+                        None => return Ok(ERROR.to_string()),
+                    };
+                    values = &values[digits..];
+                    writes.push((register, value));
+                }
+                for (register, value) in writes {
+                    self.debugger.set_register(register, value).expect("register values were checked");
+                }
+                "OK".to_string()
+            }
+            _ if packet.starts_with('p') => {
+                match parse_hex(&packet[1..]).and_then(|number| Register::all().get(number as usize).cloned()) {
+                    Some(register) => self.encode_register(register),
+                    None => ERROR.to_string(),
+                }
+            }
+            _ if packet.starts_with('P') => {
+                let write = parse_pair(&packet[1..], '=').and_then(|(number, value)| {
+                    let register = *Register::all().get(number as usize)?;
+                    Some((register, self.register_value(register, value)?))
+                });
+                match write {
+                    Some((register, value)) if self.debugger.set_register(register, value).is_ok() => "OK".to_string(),
+                    _ => ERROR.to_string(),
+                }
+            }
+            _ if packet.starts_with('m') => match parse_pair(&packet[1..], ',') {
+                //an empty reply would mean the packet is not supported
+                Some((address, length)) if (address as usize) < MEMORY_SIZE && length > 0 => {
+                    let length = (length as usize).min(MAX_READ);
+                    encode_hex(self.debugger.memory(address as u16, length))
+                }
+                _ => ERROR.to_string(),
+            },
+            _ if packet.starts_with('M') => {
+                let mut parts = packet[1..].splitn(2, ':');
+                let range = parts.next().and_then(|range| parse_pair(range, ','));
+                let bytes = parts.next().and_then(decode_hex);
+                match (range, bytes) {
+                    (Some((address, length)), Some(ref bytes))
+                        if address <= 0xFFFF && length as usize == bytes.len()
+                        && self.debugger.write_memory(address as u16, bytes) => "OK".to_string(),
+                    _ => ERROR.to_string(),
+                }
+            }
+            //hardware breakpoints are just as cheap here
+            _ if packet.starts_with("Z0,") || packet.starts_with("Z1,") || packet.starts_with("z0,") || packet.starts_with("z1,") => {
+                match packet[3..].split(',').next().and_then(parse_hex) {
+                    Some(address) if address <= 0xFFFF => {
+                        if packet.starts_with('Z') {
+                            self.debugger.add_breakpoint(address as u16);
+                        } else {
+                            self.debugger.remove_breakpoint(address as u16);
+                        }
+                        "OK".to_string()
+                    }
+                    _ => ERROR.to_string(),
+                }
+            }
+            _ => String::new(),
+        };
+
+        Ok(reply)
+    }
+
+    /// Continues until a stop or until gdb sends ctrl-c.
+    fn resume(&mut self, stream: &mut TcpStream) -> io::Result<String> {
+        let mut failure = None;
+
+        stream.set_nonblocking(true)?;
+        let stop = self.debugger.run(|| {
+            let mut byte = [0];
+            match stream.read(&mut byte) {
+                Ok(0) => true,
+                Ok(_) => byte[0] == INTERRUPT,
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => false,
+                Err(err) => {
+                    failure = Some(err);
+                    true
+                }
+            }
+        });
+        stream.set_nonblocking(false)?;
+
+        if let Some(err) = failure {
+            return Err(err);
+        }
+        if stop == Stop::WaitingForKey {
+            let message = encode_hex(b"waiting for a key, press one with `monitor key <hex>`\n");
+            self.send(stream, &format!("O{}", message))?;
+        }
+
+        Ok(self.stop_reply(stop))
+    }
+
+    fn stop_reply(&self, stop: Stop) -> String {
+        match stop {
+            Stop::Breakpoint(_) => format!("T{:02x}swbreak:;", SIGTRAP),
+            Stop::Stepped | Stop::WaitingForKey | Stop::Halted => format!("S{:02x}", SIGTRAP),
+            Stop::Interrupted => format!("S{:02x}", SIGINT),
+            Stop::Fault(Chip8Error::UnknownOpcode { .. }) => format!("S{:02x}", SIGILL),
+            Stop::Fault(_) => format!("S{:02x}", SIGSEGV),
+        }
+    }
+
+    /// Runs a `monitor` command, returns its output.
+    fn monitor(&mut self, command: &str) -> String {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        let (key, pressed) = match words.as_slice() {
+            ["key", key] => (key, true),
+            ["key", key, "down"] => (key, true),
+            ["key", key, "up"] => (key, false),
+            _ => return MONITOR_HELP.to_string(),
+        };
+
+        match u8::from_str_radix(key, 16) {
+            Ok(key) if key <= 0xF => {
+                self.debugger.chip8_mut().set_key(key, pressed);
+                String::new()
+            }
+            _ => format!("invalid key {}\n", key),
+        }
+    }
+
+    /// `value` if it fits into `register`, see `Debugger::check_register`.
+    fn register_value(&self, register: Register, value: u32) -> Option<u16> {
+        let value = u16::try_from(value).ok()?;
+        self.debugger.check_register(register, value).ok()?;
+        Some(value)
+    }
+
+    fn encode_register(&self, register: Register) -> String {
+        format!("{:01$x}", self.debugger.register(register), width(register) * 2)
+    }
+
+    fn send(&mut self, stream: &mut TcpStream, data: &str) -> io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        self.last = format!("${}#{:02x}", data, checksum).into_bytes();
+        stream.write_all(&self.last)
+    }
+}
+
+/// Bytes per register in packets.
+fn width(register: Register) -> usize {
+    match register {
+        Register::I | Register::Pc => 2,
+        _ => 1,
+    }
+}
+
+fn read_packet(stream: &mut TcpStream) -> io::Result<Incoming> {
+    let mut byte = [0];
+    let mut next = || -> io::Result<Option<u8>> {
+        match stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    };
+
+    //anything before the start of a packet is noise
+    loop {
+        match next()? {
+            None => return Ok(Incoming::Closed),
+            Some(b'$') => break,
+            Some(b'+') => return Ok(Incoming::Ack),
+            Some(b'-') => return Ok(Incoming::Nack),
+            Some(INTERRUPT) => return Ok(Incoming::Interrupt),
+            Some(_) => (),
+        }
+    }
+
+    let mut data = Vec::new();
+    loop {
+        match next()? {
+            None => return Ok(Incoming::Closed),
+            Some(b'#') => break,
+            Some(byte) if data.len() < PACKET_SIZE => data.push(byte),
+            Some(_) => (),
+        }
+    }
+
+    let mut checksum = [0; 2];
+    for digit in &mut checksum {
+        *digit = match next()? {
+            Some(byte) => byte,
+            None => return Ok(Incoming::Closed),
+        };
+    }
+
+    let expected = std::str::from_utf8(&checksum).ok().and_then(|digits| u8::from_str_radix(digits, 16).ok());
+    let actual = data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    if expected != Some(actual) {
+        return Ok(Incoming::Corrupt);
+    }
+
+    Ok(Incoming::Packet(String::from_utf8_lossy(&data).into_owned()))
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
+
+/// Parses two hex numbers separated by `separator`, e.g. `addr,length`.
+fn parse_pair(text: &str, separator: char) -> Option<(u32, u32)> {
+    let mut parts = text.splitn(2, separator);
+    Some((parse_hex(parts.next()?)?, parse_hex(parts.next()?)?))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8::Chip8;
+    use std::net::TcpListener;
+
+    /// A server for a machine running `rom` and a connected stream for
+    /// `handle` to reply on.
+    fn server(rom: &[u8]) -> (GdbServer, TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        (GdbServer::new(Debugger::new(Chip8::from_rom(rom).unwrap())), stream, client)
+    }
+
+    fn handle(server: &mut GdbServer, stream: &mut TcpStream, packet: &str) -> String {
+        server.handle(packet, stream).unwrap()
+    }
+
+    #[test]
+    fn reads_and_writes_single_registers() {
+        let (mut server, mut stream, _client) = server(&[0x00, 0xE0]);
+
+        assert_eq!(handle(&mut server, &mut stream, "P3=2a"), "OK");
+        assert_eq!(handle(&mut server, &mut stream, "p3"), "2a");
+        assert_eq!(handle(&mut server, &mut stream, "P10=0abc"), "OK");
+        assert_eq!(handle(&mut server, &mut stream, "p10"), "0abc");
+        assert_eq!(handle(&mut server, &mut stream, "p11"), "0200");
+        assert_eq!(handle(&mut server, &mut stream, "p15"), "E01");
+    }
+
+    #[test]
+    fn rejects_register_values_that_do_not_fit() {
+        let (mut server, mut stream, _client) = server(&[0x00, 0xE0]);
+
+        assert_eq!(handle(&mut server, &mut stream, "P11=10204"), "E01");
+        assert_eq!(handle(&mut server, &mut stream, "P11=1000"), "E01");
+        assert_eq!(handle(&mut server, &mut stream, "P3=100"), "E01");
+        assert_eq!(handle(&mut server, &mut stream, "P13=100"), "E01");
+        assert_eq!(handle(&mut server, &mut stream, "P15=1"), "E01");
+        assert_eq!(handle(&mut server, &mut stream, "c10204"), "E01");
+
+        let debugger = server.debugger();
+        assert_eq!(debugger.register(Register::Pc), 0x200);
+        assert_eq!(debugger.register(Register::V(3)), 0);
+        assert_eq!(debugger.register(Register::Dt), 0);
+    }
+
+    #[test]
+    fn writes_all_registers_or_none() {
+        let (mut server, mut stream, _client) = server(&[0x00, 0xE0]);
+        let registers: String = (0..16).map(|x| format!("{:02x}", x)).collect();
+
+        let packet = format!("G{}0123030001020a", registers);
+        assert_eq!(handle(&mut server, &mut stream, &packet), "OK");
+        assert_eq!(handle(&mut server, &mut stream, "g"), &packet[1..]);
+
+        //the PC is outside of memory
+        let packet = format!("G{}0456100000000b", registers.replace("0f", "ff"));
+        assert_eq!(handle(&mut server, &mut stream, &packet), "E01");
+        let packet = format!("G{}0456030000", registers);
+        assert_eq!(handle(&mut server, &mut stream, &packet), "E01");
+        assert_eq!(server.debugger().register(Register::V(0xF)), 0x0F);
+        assert_eq!(server.debugger().register(Register::I), 0x123);
+    }
+
+    #[test]
+    fn reads_and_writes_memory() {
+        let (mut server, mut stream, _client) = server(&[0x00, 0xE0]);
+
+        assert_eq!(handle(&mut server, &mut stream, "m200,2"), "00e0");
+        assert_eq!(handle(&mut server, &mut stream, "M300,3:a1b2c3"), "OK");
+        assert_eq!(handle(&mut server, &mut stream, "m300,3"), "a1b2c3");
+        assert_eq!(handle(&mut server, &mut stream, "mffe,10"), "0000");
+
+        //lengths that do not match the data or writes past the end
+        assert_eq!(handle(&mut server, &mut stream, "M300,2:a1b2c3"), "E01");
+        assert_eq!(handle(&mut server, &mut stream, "Mfff,2:a1b2"), "E01");
+        assert_eq!(handle(&mut server, &mut stream, "M300,1:zz"), "E01");
+    }
+
+    #[test]
+    fn rejects_reads_outside_of_memory() {
+        let (mut server, mut stream, _client) = server(&[0x00, 0xE0]);
+
+        assert_eq!(handle(&mut server, &mut stream, "mfff0,10"), "E01");
+        assert_eq!(handle(&mut server, &mut stream, "m1000,1"), "E01");
+        assert_eq!(handle(&mut server, &mut stream, "m200"), "E01");
+    }
+
+    #[test]
+    fn limits_reads_to_the_packet_size() {
+        let (mut server, mut stream, _client) = server(&[0x00, 0xE0]);
+
+        let reply = handle(&mut server, &mut stream, "m0,ffffffff");
+        assert_eq!(reply.len(), PACKET_SIZE);
+    }
+}
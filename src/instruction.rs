@@ -0,0 +1,266 @@
+use std::fmt;
+
+/// A decoded CHIP-8 instruction with its operands.
+///
+/// `x` and `y` are register indices, `addr` is a 12 bit address, `byte`
+/// an 8 bit immediate and `n` the 4 bit sprite height of DXYN.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// 0NNN, machine code routine on the original hardware
+    Sys { addr: u16 },
+    /// 00E0
+    Cls,
+    /// 00EE
+    Ret,
+    /// 1NNN
+    Jmp { addr: u16 },
+    /// 2NNN
+    Call { addr: u16 },
+    /// 3XNN
+    SeVxByte { x: u8, byte: u8 },
+    /// 4XNN
+    SneVxByte { x: u8, byte: u8 },
+    /// 5XY0
+    SeVxVy { x: u8, y: u8 },
+    /// 6XNN
+    LdVxByte { x: u8, byte: u8 },
+    /// 7XNN
+    AddVxByte { x: u8, byte: u8 },
+    /// 8XY0
+    LdVxVy { x: u8, y: u8 },
+    /// 8XY1
+    OrVxVy { x: u8, y: u8 },
+    /// 8XY2
+    AndVxVy { x: u8, y: u8 },
+    /// 8XY3
+    XorVxVy { x: u8, y: u8 },
+    /// 8XY4
+    AddVxVy { x: u8, y: u8 },
+    /// 8XY5
+    SubVxVy { x: u8, y: u8 },
+    /// 8XY6
+    ShrVxVy { x: u8, y: u8 },
+    /// 8XY7
+    SubnVxVy { x: u8, y: u8 },
+    /// 8XYE
+    ShlVxVy { x: u8, y: u8 },
+    /// 9XY0
+    SneVxVy { x: u8, y: u8 },
+    /// ANNN
+    LdI { addr: u16 },
+    /// BNNN
+    JmpV0 { addr: u16 },
+    /// CXNN
+    Rnd { x: u8, byte: u8 },
+    /// DXYN
+    Drw { x: u8, y: u8, n: u8 },
+    /// EX9E
+    Skp { x: u8 },
+    /// EXA1
+    Sknp { x: u8 },
+    /// FX07
+    LdVxDt { x: u8 },
+    /// FX0A
+    LdVxK { x: u8 },
+    /// FX15
+    LdDtVx { x: u8 },
+    /// FX18
+    LdStVx { x: u8 },
+    /// FX1E
+    AddIVx { x: u8 },
+    /// FX29
+    LdFVx { x: u8 },
+    /// FX33
+    LdBVx { x: u8 },
+    /// FX55
+    LdIVx { x: u8 },
+    /// FX65
+    LdVxI { x: u8 },
+    /// Any opcode that is not a CHIP-8 instruction.
+    Unknown { opcode: u16 },
+}
+
+/// Decodes a raw big endian opcode.
+pub fn decode(opcode: u16) -> Instruction {
+    let addr = opcode & 0x0FFF;
+    let byte = (opcode & 0x00FF) as u8;
+    let x = ((opcode & 0x0F00) >> 8) as u8;
+    let y = ((opcode & 0x00F0) >> 4) as u8;
+    let n = (opcode & 0x000F) as u8;
+
+    match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 => Instruction::Cls,
+            0x00EE => Instruction::Ret,
+            _ => Instruction::Sys { addr },
+        },
+        0x1000 => Instruction::Jmp { addr },
+        0x2000 => Instruction::Call { addr },
+        0x3000 => Instruction::SeVxByte { x, byte },
+        0x4000 => Instruction::SneVxByte { x, byte },
+        0x5000 if n == 0 => Instruction::SeVxVy { x, y },
+        0x6000 => Instruction::LdVxByte { x, byte },
+        0x7000 => Instruction::AddVxByte { x, byte },
+        0x8000 => match n {
+            0x0 => Instruction::LdVxVy { x, y },
+            0x1 => Instruction::OrVxVy { x, y },
+            0x2 => Instruction::AndVxVy { x, y },
+            0x3 => Instruction::XorVxVy { x, y },
+            0x4 => Instruction::AddVxVy { x, y },
+            0x5 => Instruction::SubVxVy { x, y },
+            0x6 => Instruction::ShrVxVy { x, y },
+            0x7 => Instruction::SubnVxVy { x, y },
+            0xE => Instruction::ShlVxVy { x, y },
+            _ => Instruction::Unknown { opcode },
+        },
+        0x9000 if n == 0 => Instruction::SneVxVy { x, y },
+        0xA000 => Instruction::LdI { addr },
+        0xB000 => Instruction::JmpV0 { addr },
+        0xC000 => Instruction::Rnd { x, byte },
+        0xD000 => Instruction::Drw { x, y, n },
+        0xE000 => match byte {
+            0x9E => Instruction::Skp { x },
+            0xA1 => Instruction::Sknp { x },
+            _ => Instruction::Unknown { opcode },
+        },
+        0xF000 => match byte {
+            0x07 => Instruction::LdVxDt { x },
+            0x0A => Instruction::LdVxK { x },
+            0x15 => Instruction::LdDtVx { x },
+            0x18 => Instruction::LdStVx { x },
+            0x1E => Instruction::AddIVx { x },
+            0x29 => Instruction::LdFVx { x },
+            0x33 => Instruction::LdBVx { x },
+            0x55 => Instruction::LdIVx { x },
+            0x65 => Instruction::LdVxI { x },
+            _ => Instruction::Unknown { opcode },
+        },
+        _ => Instruction::Unknown { opcode },
+    }
+}
+
+impl Instruction {
+    /// The address operand of jumps, calls and loads into I.
+    pub fn address(&self) -> Option<u16> {
+        match *self {
+            Instruction::Sys { addr }
+            | Instruction::Jmp { addr }
+            | Instruction::Call { addr }
+            | Instruction::LdI { addr }
+            | Instruction::JmpV0 { addr } => Some(addr),
+            _ => None,
+        }
+    }
+
+    /// Whether the instruction may skip the one following it.
+    pub fn is_skip(&self) -> bool {
+        matches!(*self,
+            Instruction::SeVxByte { .. }
+            | Instruction::SneVxByte { .. }
+            | Instruction::SeVxVy { .. }
+            | Instruction::SneVxVy { .. }
+            | Instruction::Skp { .. }
+            | Instruction::Sknp { .. })
+    }
+
+    /// Encodes the instruction back into its opcode, the inverse of `decode`.
+    ///
+    /// Operands are masked to their field width.
+    pub fn encode(&self) -> u16 {
+        let xy = |base: u16, x: u8, y: u8| base | (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4;
+        let xb = |base: u16, x: u8, byte: u8| base | (x as u16 & 0xF) << 8 | byte as u16;
+        let nnn = |base: u16, addr: u16| base | (addr & 0x0FFF);
+
+        match *self {
+            Instruction::Sys { addr } => nnn(0x0000, addr),
+            Instruction::Cls => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::Jmp { addr } => nnn(0x1000, addr),
+            Instruction::Call { addr } => nnn(0x2000, addr),
+            Instruction::SeVxByte { x, byte } => xb(0x3000, x, byte),
+            Instruction::SneVxByte { x, byte } => xb(0x4000, x, byte),
+            Instruction::SeVxVy { x, y } => xy(0x5000, x, y),
+            Instruction::LdVxByte { x, byte } => xb(0x6000, x, byte),
+            Instruction::AddVxByte { x, byte } => xb(0x7000, x, byte),
+            Instruction::LdVxVy { x, y } => xy(0x8000, x, y),
+            Instruction::OrVxVy { x, y } => xy(0x8001, x, y),
+            Instruction::AndVxVy { x, y } => xy(0x8002, x, y),
+            Instruction::XorVxVy { x, y } => xy(0x8003, x, y),
+            Instruction::AddVxVy { x, y } => xy(0x8004, x, y),
+            Instruction::SubVxVy { x, y } => xy(0x8005, x, y),
+            Instruction::ShrVxVy { x, y } => xy(0x8006, x, y),
+            Instruction::SubnVxVy { x, y } => xy(0x8007, x, y),
+            Instruction::ShlVxVy { x, y } => xy(0x800E, x, y),
+            Instruction::SneVxVy { x, y } => xy(0x9000, x, y),
+            Instruction::LdI { addr } => nnn(0xA000, addr),
+            Instruction::JmpV0 { addr } => nnn(0xB000, addr),
+            Instruction::Rnd { x, byte } => xb(0xC000, x, byte),
+            Instruction::Drw { x, y, n } => xy(0xD000, x, y) | (n as u16 & 0xF),
+            Instruction::Skp { x } => xb(0xE09E, x, 0),
+            Instruction::Sknp { x } => xb(0xE0A1, x, 0),
+            Instruction::LdVxDt { x } => xb(0xF007, x, 0),
+            Instruction::LdVxK { x } => xb(0xF00A, x, 0),
+            Instruction::LdDtVx { x } => xb(0xF015, x, 0),
+            Instruction::LdStVx { x } => xb(0xF018, x, 0),
+            Instruction::AddIVx { x } => xb(0xF01E, x, 0),
+            Instruction::LdFVx { x } => xb(0xF029, x, 0),
+            Instruction::LdBVx { x } => xb(0xF033, x, 0),
+            Instruction::LdIVx { x } => xb(0xF055, x, 0),
+            Instruction::LdVxI { x } => xb(0xF065, x, 0),
+            Instruction::Unknown { opcode } => opcode,
+        }
+    }
+
+    /// Writes the mnemonic, printing the address operand with `addr`.
+    pub fn write_mnemonic<W, F>(&self, out: &mut W, addr: F) -> fmt::Result
+    where
+        W: fmt::Write,
+        F: Fn(u16) -> String,
+    {
+        match *self {
+            Instruction::Sys { addr: a } => write!(out, "SYS {}", addr(a)),
+            Instruction::Cls => write!(out, "CLS"),
+            Instruction::Ret => write!(out, "RET"),
+            Instruction::Jmp { addr: a } => write!(out, "JP {}", addr(a)),
+            Instruction::Call { addr: a } => write!(out, "CALL {}", addr(a)),
+            Instruction::SeVxByte { x, byte } => write!(out, "SE V{:X}, 0x{:02X}", x, byte),
+            Instruction::SneVxByte { x, byte } => write!(out, "SNE V{:X}, 0x{:02X}", x, byte),
+            Instruction::SeVxVy { x, y } => write!(out, "SE V{:X}, V{:X}", x, y),
+            Instruction::LdVxByte { x, byte } => write!(out, "LD V{:X}, 0x{:02X}", x, byte),
+            Instruction::AddVxByte { x, byte } => write!(out, "ADD V{:X}, 0x{:02X}", x, byte),
+            Instruction::LdVxVy { x, y } => write!(out, "LD V{:X}, V{:X}", x, y),
+            Instruction::OrVxVy { x, y } => write!(out, "OR V{:X}, V{:X}", x, y),
+            Instruction::AndVxVy { x, y } => write!(out, "AND V{:X}, V{:X}", x, y),
+            Instruction::XorVxVy { x, y } => write!(out, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddVxVy { x, y } => write!(out, "ADD V{:X}, V{:X}", x, y),
+            Instruction::SubVxVy { x, y } => write!(out, "SUB V{:X}, V{:X}", x, y),
+            Instruction::ShrVxVy { x, y } => write!(out, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubnVxVy { x, y } => write!(out, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShlVxVy { x, y } => write!(out, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SneVxVy { x, y } => write!(out, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LdI { addr: a } => write!(out, "LD I, {}", addr(a)),
+            Instruction::JmpV0 { addr: a } => write!(out, "JP V0, {}", addr(a)),
+            Instruction::Rnd { x, byte } => write!(out, "RND V{:X}, 0x{:02X}", x, byte),
+            Instruction::Drw { x, y, n } => write!(out, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::Skp { x } => write!(out, "SKP V{:X}", x),
+            Instruction::Sknp { x } => write!(out, "SKNP V{:X}", x),
+            Instruction::LdVxDt { x } => write!(out, "LD V{:X}, DT", x),
+            Instruction::LdVxK { x } => write!(out, "LD V{:X}, K", x),
+            Instruction::LdDtVx { x } => write!(out, "LD DT, V{:X}", x),
+            Instruction::LdStVx { x } => write!(out, "LD ST, V{:X}", x),
+            Instruction::AddIVx { x } => write!(out, "ADD I, V{:X}", x),
+            Instruction::LdFVx { x } => write!(out, "LD F, V{:X}", x),
+            Instruction::LdBVx { x } => write!(out, "LD B, V{:X}", x),
+            Instruction::LdIVx { x } => write!(out, "LD [I], V{:X}", x),
+            Instruction::LdVxI { x } => write!(out, "LD V{:X}, [I]", x),
+            Instruction::Unknown { opcode } => write!(out, "DW 0x{:04X}", opcode),
+        }
+    }
+}
+
+/// Prints the mnemonic in the classic Cowgod syntax, e.g. `DRW V1, V2, 5`.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_mnemonic(f, |addr| format!("0x{:03X}", addr))
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+
+use movie::rom_hash;
+
+/// The hex keys in the order of the host keys in `LAYOUTS`, row by row:
+///
+/// ```text
+/// 1 2 3 C
+/// 4 5 6 D
+/// 7 8 9 E
+/// A 0 B F
+/// ```
+const KEYPAD: [u8; 16] = [0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF];
+
+/// The left 4x4 block of host keys for each layout. AZERTY also takes its
+/// unshifted top row, terminals report those instead of the digits.
+const LAYOUTS: [(&str, &[&str]); 3] = [
+    ("qwerty", &["1234qwerasdfzxcv"]),
+    ("qwertz", &["1234qwerasdfyxcv"]),
+    ("azerty", &["1234azerqsdfwxcv", "&é\"'azerqsdfwxcv"]),
+];
+
+/// Which host key presses which of the 16 hex keys. A hex key can have any
+/// number of host keys, host keys are compared in lower case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    bindings: BTreeMap<char, u8>,
+}
+
+impl Keymap {
+    /// `qwerty`, `qwertz` or `azerty`.
+    pub fn preset(name: &str) -> Option<Keymap> {
+        let &(_, rows) = LAYOUTS.iter().find(|&&(layout, _)| layout == name)?;
+
+        let mut keymap = Keymap { bindings: BTreeMap::new() };
+        for row in rows {
+            for (host, &key) in row.chars().zip(KEYPAD.iter()) {
+                keymap.bindings.insert(host, key);
+            }
+        }
+
+        Some(keymap)
+    }
+
+    /// The hex key `host` is bound to.
+    pub fn key(&self, host: char) -> Option<u8> {
+        self.bindings.get(&lower(host)).cloned()
+    }
+
+    /// The host keys bound to the hex key `key`.
+    pub fn host_keys(&self, key: u8) -> Vec<char> {
+        self.bindings.iter().filter(|&(_, &bound)| bound == key).map(|(&host, _)| host).collect()
+    }
+
+    /// Makes `hosts` the only host keys of `key`, taking them from the hex
+    /// keys they were bound to before.
+    pub fn bind(&mut self, key: u8, hosts: &[char]) {
+        let key = key & 0xF;
+        self.bindings.retain(|_, bound| *bound != key);
+        for &host in hosts {
+            self.bindings.insert(lower(host), key);
+        }
+    }
+}
+
+impl Default for Keymap {
+    /// The QWERTZ preset, the layout the emulator always had.
+    fn default() -> Keymap {
+        Keymap::preset("qwertz").unwrap()
+    }
+}
+
+/// An error in a keymap file with the 1 based line it occurred on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeymapError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for KeymapError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Directive {
+    Layout(Keymap),
+    Bind(u8, Vec<char>),
+}
+
+/// A keymap file: a layout, changes to it and changes for single ROMs.
+///
+/// ```text
+/// # the layout of the physical keyboard
+/// layout qwertz
+/// # a hex key and all host keys that press it, `space` for the space bar
+/// key 5 w space
+///
+/// # only for this ROM, by file name or CRC-32
+/// rom Pong 2
+/// key 1 w
+/// key 4 s
+/// key c i
+/// key d k
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymaps {
+    global: Vec<Directive>,
+    roms: Vec<(String, Vec<Directive>)>,
+}
+
+impl Keymaps {
+    pub fn parse(text: &str) -> Result<Keymaps, KeymapError> {
+        let mut keymaps = Keymaps { global: Vec::new(), roms: Vec::new() };
+
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| KeymapError { line: index + 1, message };
+
+            let code = line.split('#').next().unwrap_or("");
+            let words: Vec<&str> = code.split_whitespace().collect();
+
+            let directive = match words.as_slice() {
+                [] => continue,
+                //the rest of the line, file names may contain spaces
+                ["rom", _, ..] => {
+                    let name = code.trim()["rom".len()..].trim();
+                    keymaps.roms.push((name.to_lowercase(), Vec::new()));
+                    continue;
+                }
+                ["layout", name] => match Keymap::preset(name) {
+                    Some(keymap) => Directive::Layout(keymap),
+                    None => return Err(error(format!("unknown layout {}, expected qwerty, qwertz or azerty", name))),
+                },
+                ["key", key, hosts @ ..] if !hosts.is_empty() => {
+                    let key = match u8::from_str_radix(key, 16) {
+                        Ok(key) if key <= 0xF => key,
+                        _ => return Err(error(format!("invalid key {}", key))),
+                    };
+                    let hosts = hosts.iter().map(|host| host_key(host).ok_or_else(|| error(format!("invalid host key {}", host))));
+                    Directive::Bind(key, hosts.collect::<Result<_, _>>()?)
+                }
+                _ => return Err(error(format!("unexpected {}", code.trim()))),
+            };
+
+            match keymaps.roms.last_mut() {
+                Some(&mut (_, ref mut directives)) => directives.push(directive),
+                None => keymaps.global.push(directive),
+            }
+        }
+
+        Ok(keymaps)
+    }
+
+    /// The keymap for a ROM, `name` is its file name without extension.
+    pub fn keymap(&self, name: &str, rom: &[u8]) -> Keymap {
+        let name = name.to_lowercase();
+        let hash = format!("{:08x}", rom_hash(rom));
+
+        let mut keymap = Keymap::default();
+        let sections = self.roms.iter().filter(|&(rom, _)| *rom == name || *rom == hash);
+        for directive in self.global.iter().chain(sections.flat_map(|(_, directives)| directives)) {
+            match *directive {
+                Directive::Layout(ref layout) => keymap = layout.clone(),
+                Directive::Bind(key, ref hosts) => keymap.bind(key, hosts),
+            }
+        }
+
+        keymap
+    }
+}
+
+fn host_key(name: &str) -> Option<char> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        _ if name == "space" => Some(' '),
+        (Some(host), None) => Some(lower(host)),
+        _ => None,
+    }
+}
+
+fn lower(host: char) -> char {
+    host.to_lowercase().next().unwrap_or(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_cover_the_keypad() {
+        for &(name, _) in LAYOUTS.iter() {
+            let keymap = Keymap::preset(name).unwrap();
+            assert!((0..16).all(|key| !keymap.host_keys(key).is_empty()), "{}", name);
+        }
+
+        let qwertz = Keymap::default();
+        assert_eq!((qwertz.key('y'), qwertz.key('Z')), (Some(0xA), None));
+        let qwerty = Keymap::preset("qwerty").unwrap();
+        assert_eq!((qwerty.key('Z'), qwerty.key('y')), (Some(0xA), None));
+        let azerty = Keymap::preset("azerty").unwrap();
+        assert_eq!((azerty.key('1'), azerty.key('&'), azerty.key('a')), (Some(0x1), Some(0x1), Some(0x4)));
+        assert_eq!(Keymap::preset("dvorak"), None);
+    }
+
+    #[test]
+    fn bind_moves_host_keys() {
+        let mut keymap = Keymap::default();
+        keymap.bind(0x5, &['W', ' ']);
+
+        assert_eq!(keymap.host_keys(0x5), [' ', 'w']);
+        assert_eq!((keymap.key('w'), keymap.key(' ')), (Some(0x5), Some(0x5)));
+        assert_eq!(keymap.host_keys(0x4), ['q']);
+    }
+
+    #[test]
+    fn files_apply_global_then_rom_sections() {
+        let rom = [0x12, 0x00];
+        let text = format!(
+            "layout qwerty  # comment\nkey 5 w space\n\nrom Pong 2\nkey 1 i\nrom {:08x}\nkey 2 k\n",
+            rom_hash(&rom)
+        );
+        let keymaps = Keymaps::parse(&text).unwrap();
+
+        let other = keymaps.keymap("brix", &[0x00, 0xE0]);
+        assert_eq!((other.key('z'), other.key(' '), other.key('i'), other.key('k')), (Some(0xA), Some(0x5), None, None));
+
+        let pong = keymaps.keymap("PONG 2", &rom);
+        assert_eq!((pong.key(' '), pong.key('i'), pong.key('k'), pong.key('1')), (Some(0x5), Some(0x1), Some(0x2), None));
+    }
+
+    #[test]
+    fn errors_carry_the_line_number() {
+        let error = |text: &str| Keymaps::parse(text).unwrap_err();
+
+        assert_eq!(error("layout qwerty\nlayout dvorak").line, 2);
+        assert_eq!(error("layout dvorak").message, "unknown layout dvorak, expected qwerty, qwertz or azerty");
+        assert_eq!(error("\nkey 10 w"), KeymapError { line: 2, message: "invalid key 10".to_string() });
+        assert_eq!(error("key 1 ctrl").message, "invalid host key ctrl");
+        assert_eq!(error("key 1").message, "unexpected key 1");
+        assert_eq!(error("rom\n").message, "unexpected rom");
+    }
+}
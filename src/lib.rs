@@ -0,0 +1,35 @@
+pub mod asm;
+pub mod audio;
+pub mod chip8;
+pub mod clock;
+pub mod config;
+pub mod debugger;
+pub mod disasm;
+pub mod error;
+pub mod frontend;
+pub mod gdb;
+pub mod instruction;
+pub mod keymap;
+pub mod movie;
+pub mod octo;
+pub mod quirks;
+pub mod random;
+pub mod rewind;
+pub mod screenshot;
+pub mod state;
+pub mod trace;
+
+pub use audio::{AudioSink, SquareWave};
+pub use chip8::{Chip8, FrameOutcome, StepOutcome};
+pub use clock::Clock;
+pub use config::Config;
+pub use debugger::Debugger;
+pub use error::{Chip8Error, DriverError, LoadError, MovieError, StateError};
+pub use frontend::{DisplaySink, Driver, Input, InputSource, KeyScript, TimingSource};
+pub use instruction::{decode, Instruction};
+pub use keymap::{Keymap, Keymaps};
+pub use movie::{KeyEvent, Movie, Player};
+pub use quirks::{LoadStoreIndex, Quirks};
+pub use random::{RandomSource, XorShift};
+pub use rewind::Rewind;
+pub use trace::{TraceEntry, TraceFormat, TraceSink, TraceWriter};
@@ -0,0 +1,311 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chip8::Chip8;
+use config::Config;
+use error::MovieError;
+use frontend::KeyScript;
+use quirks::Quirks;
+use state::crc32;
+
+/// Format version written by `Movie`'s `Display`.
+pub const MOVIE_VERSION: u32 = 1;
+
+const HEADER: &str = "chip8-movie";
+
+/// A key going down or up before the given frame ran.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub frame: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+/// A recorded session: the ROM it was played on, the machine's config and
+/// every key event, enough to replay it exactly.
+///
+/// Movies are plain text so they can be attached to bug reports:
+///
+/// ```text
+/// chip8-movie 1
+/// rom 8a2f6c1e
+/// seed 42
+/// speed 700
+/// quirks clip_sprites key_wait_release
+/// frames 3600
+/// gfx 1c291ca3
+/// 120 5 down
+/// 131 5 up
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    /// CRC-32 of the ROM.
+    pub rom_hash: u32,
+    pub config: Config,
+    /// Length of the recording.
+    pub frames: u64,
+    /// CRC-32 of the display after the last frame, to verify a replay.
+    pub gfx_hash: u32,
+    pub events: Vec<KeyEvent>,
+}
+
+pub fn rom_hash(rom: &[u8]) -> u32 {
+    crc32(rom)
+}
+
+pub fn gfx_hash(gfx: &[u8]) -> u32 {
+    crc32(gfx)
+}
+
+impl Chip8 {
+    /// Starts logging key events for a movie.
+    ///
+    /// Replays start from power on, so record from a freshly created
+    /// machine.
+    pub fn start_recording(&mut self) {
+        self.input_log = Some(Vec::new());
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.input_log.is_some()
+    }
+
+    /// Ends the recording, `rom` is the ROM the machine was created with.
+    pub fn stop_recording(&mut self, rom: &[u8]) -> Movie {
+        Movie {
+            rom_hash: rom_hash(rom),
+            config: self.config(),
+            frames: self.frame,
+            gfx_hash: gfx_hash(self.get_gfx()),
+            events: self.input_log.take().unwrap_or_default(),
+        }
+    }
+}
+
+impl Movie {
+    pub fn parse(text: &str) -> Result<Movie, MovieError> {
+        let mut movie = Movie {
+            rom_hash: 0,
+            config: Config::default(),
+            frames: 0,
+            gfx_hash: 0,
+            events: Vec::new(),
+        };
+
+        let mut lines = text.lines().enumerate().map(|(index, line)| (index + 1, line.trim()));
+
+        match lines.next() {
+            Some((_, header)) if header.split_whitespace().next() == Some(HEADER) => {
+                let version = header.split_whitespace().nth(1).and_then(|version| version.parse().ok());
+                if version != Some(MOVIE_VERSION) {
+                    return Err(parse_error(1, format!("unsupported movie version in {}", header)));
+                }
+            }
+            _ => return Err(parse_error(1, "not a movie file".to_string())),
+        }
+
+        for (line, text) in lines {
+            let words: Vec<&str> = text.split_whitespace().collect();
+            let error = |message: String| parse_error(line, message);
+
+            match words.as_slice() {
+                [] => (),
+                ["rom", hash] => movie.rom_hash = hex(hash).map_err(error)?,
+                ["gfx", hash] => movie.gfx_hash = hex(hash).map_err(error)?,
+                ["seed", seed] => movie.config.seed = decimal(seed).map_err(error)?,
+                ["speed", speed] => movie.config.instructions_per_second = decimal(speed).map_err(error)?,
+                ["frames", frames] => movie.frames = decimal(frames).map_err(error)?,
+                ["quirks", names @ ..] => {
+                    let mut quirks = Quirks::default();
+                    for name in names {
+                        if !quirks.set(name, true) {
+                            return Err(error(format!("unknown quirk {}", name)));
+                        }
+                    }
+                    movie.config.quirks = quirks;
+                }
+                [frame, key, state] => {
+                    let frame = decimal(frame).map_err(error)?;
+                    let key = match u8::from_str_radix(key, 16) {
+                        Ok(key) if key <= 0xF => key,
+                        _ => return Err(error(format!("invalid key {}", key))),
+                    };
+                    let pressed = match *state {
+                        "down" => true,
+                        "up" => false,
+                        _ => return Err(error(format!("expected down or up, found {}", state))),
+                    };
+                    if movie.events.last().is_some_and(|last| last.frame > frame) {
+                        return Err(error("key events are out of order".to_string()));
+                    }
+                    movie.events.push(KeyEvent { frame, key, pressed });
+                }
+                _ => return Err(error(format!("unexpected {}", text))),
+            }
+        }
+
+        Ok(movie)
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} {}", HEADER, MOVIE_VERSION)?;
+        writeln!(f, "rom {:08x}", self.rom_hash)?;
+        writeln!(f, "seed {}", self.config.seed)?;
+        writeln!(f, "speed {}", self.config.instructions_per_second)?;
+
+        write!(f, "quirks")?;
+        for name in self.config.quirks.enabled() {
+            write!(f, " {}", name)?;
+        }
+        writeln!(f)?;
+
+        writeln!(f, "frames {}", self.frames)?;
+        writeln!(f, "gfx {:08x}", self.gfx_hash)?;
+
+        for event in &self.events {
+            let state = if event.pressed { "down" } else { "up" };
+            writeln!(f, "{} {:X} {}", event.frame, event.key, state)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Replays a movie into a machine, frame by frame.
+pub struct Player {
+    movie: Movie,
+    script: KeyScript,
+}
+
+impl Player {
+    pub fn new(movie: Movie) -> Player {
+        Player { script: KeyScript::new(movie.events.clone()), movie }
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    /// Fails if the movie was recorded with a different ROM.
+    pub fn check_rom(&self, rom: &[u8]) -> Result<(), MovieError> {
+        let actual = rom_hash(rom);
+        if actual != self.movie.rom_hash {
+            return Err(MovieError::RomMismatch { expected: self.movie.rom_hash, actual });
+        }
+        Ok(())
+    }
+
+    /// Applies the key events of the coming frame, call before every
+    /// `run_frame`. The machine has to be created with the movie's config.
+    pub fn feed(&mut self, chip8: &mut Chip8) {
+        for event in self.script.due(chip8.frame_count()) {
+            chip8.set_key(event.key, event.pressed);
+        }
+    }
+
+    /// The events of the coming frame that were not applied yet.
+    pub fn due(&mut self, frame: u64) -> &[KeyEvent] {
+        self.script.due(frame)
+    }
+
+    pub fn is_finished(&self, chip8: &Chip8) -> bool {
+        chip8.frame_count() >= self.movie.frames
+    }
+
+    /// Whether the display matches the one at the end of the recording.
+    pub fn matches(&self, chip8: &Chip8) -> bool {
+        gfx_hash(chip8.get_gfx()) == self.movie.gfx_hash
+    }
+}
+
+fn parse_error(line: usize, message: String) -> MovieError {
+    MovieError::Parse { line, message }
+}
+
+fn hex(text: &str) -> Result<u32, String> {
+    u32::from_str_radix(text, 16).map_err(|_| format!("invalid hash {}", text))
+}
+
+fn decimal<T: FromStr>(text: &str) -> Result<T, String> {
+    text.parse().map_err(|_| format!("invalid number {}", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use frontend::{Driver, Unthrottled};
+
+    /// Waits for a key, adds it to V2 and draws the digit of V2 at a
+    /// random column, so the display depends on every key and the seed.
+    const ROM: &[u8] = &[
+        0xF1, 0x0A, 0x82, 0x14, 0xC3, 0x3F, 0xF2, 0x29, 0xD3, 0x45, 0x12, 0x00,
+    ];
+
+    fn recording() -> (Chip8, Movie) {
+        let config = Config { seed: 99, instructions_per_second: 610, ..Config::default() };
+        let mut chip8 = Chip8::with_config(ROM, config).unwrap();
+        chip8.start_recording();
+
+        for frame in 0..90 {
+            match frame {
+                5 | 40 => chip8.key_down(0x7),
+                6 | 41 => chip8.key_up(0x7),
+                20 => chip8.key_down(0xA),
+                21 => {
+                    chip8.key_down(0x3);
+                    chip8.key_up(0xA);
+                }
+                30 => chip8.key_up(0x3),
+                _ => (),
+            }
+            chip8.run_frame().unwrap();
+        }
+
+        let movie = chip8.stop_recording(ROM);
+        (chip8, movie)
+    }
+
+    #[test]
+    fn written_movies_parse_back() {
+        let (_, mut movie) = recording();
+        movie.config.quirks = Quirks::chip48();
+
+        let text = movie.to_string();
+        assert!(text.starts_with("chip8-movie 1\nrom "));
+        assert_eq!(Movie::parse(&text), Ok(movie));
+    }
+
+    #[test]
+    fn replays_reproduce_the_recorded_machine() {
+        let (recorded, movie) = recording();
+        assert_eq!(movie.events.len(), 8);
+
+        let movie = Movie::parse(&movie.to_string()).unwrap();
+        let player = Player::new(movie.clone());
+        player.check_rom(ROM).unwrap();
+
+        let mut chip8 = Chip8::with_config(ROM, movie.config).unwrap();
+        let mut driver = Driver::new((), player, Unthrottled).with_frame_limit(movie.frames);
+        driver.run(&mut chip8).unwrap();
+
+        assert!(driver.input.matches(&chip8));
+        assert_eq!(crc32(&chip8.save_state()), crc32(&recorded.save_state()));
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        let error = |text: &str| match Movie::parse(text) {
+            Err(MovieError::Parse { line, message }) => (line, message),
+            other => panic!("{:?}", other),
+        };
+
+        assert_eq!(error("chip8-movie 2\n"), (1, "unsupported movie version in chip8-movie 2".to_string()));
+        assert_eq!(error("speed 700\n"), (1, "not a movie file".to_string()));
+        assert_eq!(error("chip8-movie 1\nspeed 4294967396\n"), (2, "invalid number 4294967396".to_string()));
+        assert_eq!(error("chip8-movie 1\n\n3 G down\n"), (3, "invalid key G".to_string()));
+        assert_eq!(error("chip8-movie 1\n5 1 down\n3 1 up\n"), (3, "key events are out of order".to_string()));
+        assert_eq!(error("chip8-movie 1\nquirks fast\n"), (2, "unknown quirk fast".to_string()));
+    }
+}
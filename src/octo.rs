@@ -0,0 +1,979 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use chip8::{MEMORY_SIZE, PROGRAM_START};
+use instruction::Instruction;
+
+/// An Octo compile error with the 1 based line it occurred on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OctoError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for OctoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for OctoError {}
+
+/// A compiled Octo program with the symbols needed to map addresses back
+/// to the source.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Program {
+    /// The ROM image, to be loaded at 0x200.
+    pub rom: Vec<u8>,
+    pub labels: BTreeMap<String, u16>,
+    /// Source line of every statement that emitted bytes, by its first address.
+    pub lines: BTreeMap<u16, usize>,
+    pub breakpoints: BTreeMap<u16, String>,
+}
+
+impl Program {
+    /// Source line of the statement that emitted the byte at `address`.
+    pub fn line_at(&self, address: u16) -> Option<usize> {
+        if address as usize >= PROGRAM_START + self.rom.len() {
+            return None;
+        }
+        self.lines.range(..=address).next_back().map(|(_, &line)| line)
+    }
+
+    /// The closest label at or before `address` and the distance to it.
+    pub fn label_at(&self, address: u16) -> Option<(&str, u16)> {
+        self.labels
+            .iter()
+            .filter(|&(_, &label)| label <= address)
+            .max_by_key(|&(_, &label)| label)
+            .map(|(name, &label)| (name.as_str(), address - label))
+    }
+
+    /// Describes `address` for error messages, e.g. `line 12, main+4`.
+    pub fn locate(&self, address: u16) -> String {
+        let mut parts = Vec::new();
+        if let Some(line) = self.line_at(address) {
+            parts.push(format!("line {}", line));
+        }
+        match self.label_at(address) {
+            Some((name, 0)) => parts.push(name.to_string()),
+            Some((name, offset)) => parts.push(format!("{}+{}", name, offset)),
+            None => (),
+        }
+
+        if parts.is_empty() {
+            format!("0x{:03X}", address)
+        } else {
+            parts.join(", ")
+        }
+    }
+
+    /// Writes labels, breakpoints and the source map as plain text, one
+    /// `label <name> <address>`, `breakpoint <address> <name>` or
+    /// `line <address> <line>` entry per line.
+    pub fn write_symbols<W: io::Write>(&self, mut out: W) -> io::Result<()> {
+        let mut labels: Vec<_> = self.labels.iter().collect();
+        labels.sort_by_key(|&(name, &address)| (address, name));
+        for (name, address) in labels {
+            writeln!(out, "label {} 0x{:03X}", name, address)?;
+        }
+        for (address, name) in &self.breakpoints {
+            writeln!(out, "breakpoint 0x{:03X} {}", address, name)?;
+        }
+        for (address, line) in &self.lines {
+            writeln!(out, "line 0x{:03X} {}", address, line)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Compiles an Octo program into a CHIP-8 ROM.
+///
+/// Supported are labels (`: name`), `:alias`, `:const`, `:calc`, `:macro`,
+/// `:byte`, `:org`, `:unpack`, `:call` and `:breakpoint`, every CHIP-8
+/// statement, `if ... then`, `if ... begin ... else ... end` and
+/// `loop ... while ... again`. SUPER-CHIP and XO-CHIP extensions are
+/// rejected.
+///
+/// `:calc` expressions have no operator precedence and evaluate right to
+/// left like in Octo, use parentheses to group. If the program has a
+/// `main` label that is not at the very start, a jump to it is put at
+/// 0x200.
+pub fn compile(source: &str) -> Result<Program, OctoError> {
+    let tokens = tokenize(source);
+
+    let mut compiler = Compiler::new(tokens);
+
+    let main = compiler.tokens.iter().zip(compiler.tokens.iter().skip(1))
+        .position(|(colon, name)| colon.text == ":" && name.text == "main");
+    if let Some(position) = main {
+        if position > 0 {
+            compiler.jump_to("main", 0x1000).map_err(|message| OctoError { line: 1, message })?;
+        }
+    }
+
+    while let Some(token) = compiler.tokens.pop_front() {
+        compiler.line = token.line;
+        compiler.expansion = token.expansion;
+        compiler.statement(&token.text).map_err(|message| OctoError { line: compiler.line, message })?;
+    }
+
+    compiler.finish()
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    /// The macros whose expansion produced the token, innermost last.
+    expansion: Vec<String>,
+}
+
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<Token>,
+}
+
+enum Block {
+    /// `if ... begin`, the jump at `skip` leads to `else` or `end`.
+    If { skip: u16, line: usize },
+    /// After `else`, the jump at `skip` leads to `end`.
+    Else { skip: u16, line: usize },
+    /// `loop`, every `while` adds a jump to patch with the loop's end.
+    Loop { start: u16, breaks: Vec<u16>, line: usize },
+}
+
+/// A jump, call or `i :=` whose label was not yet defined.
+struct Fixup {
+    address: u16,
+    label: String,
+    line: usize,
+}
+
+struct Compiler {
+    tokens: VecDeque<Token>,
+    line: usize,
+    /// The macros expanding into the current statement.
+    expansion: Vec<String>,
+    rom: Vec<u8>,
+    here: u16,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    blocks: Vec<Block>,
+    fixups: Vec<Fixup>,
+    lines: BTreeMap<u16, usize>,
+    breakpoints: BTreeMap<u16, String>,
+}
+
+impl Compiler {
+    fn new(tokens: Vec<Token>) -> Compiler {
+        Compiler {
+            tokens: tokens.into_iter().collect(),
+            line: 1,
+            expansion: Vec::new(),
+            rom: Vec::new(),
+            here: PROGRAM_START as u16,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            blocks: Vec::new(),
+            fixups: Vec::new(),
+            lines: BTreeMap::new(),
+            breakpoints: BTreeMap::new(),
+        }
+    }
+
+    fn statement(&mut self, word: &str) -> Result<(), String> {
+        match word {
+            ":" => {
+                let name = self.name()?;
+                if self.labels.contains_key(&name) {
+                    return Err(format!("label {} is defined twice", name));
+                }
+                self.labels.insert(name, self.here);
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let token = self.next()?;
+                let register = self.register(&token).ok_or_else(|| format!("{} is not a register", token))?;
+                self.aliases.insert(name, register);
+            }
+            ":const" => {
+                let name = self.name()?;
+                let token = self.next()?;
+                let value = self.value(&token)?;
+                self.constant(name, value as f64)?;
+            }
+            ":calc" => {
+                let name = self.name()?;
+                let value = self.calc()?;
+                self.constant(name, value)?;
+            }
+            ":macro" => self.define_macro()?,
+            ":byte" => {
+                let value = if self.peek() == Some("{") {
+                    self.calc()? as i64
+                } else {
+                    let token = self.next()?;
+                    self.value(&token)?
+                };
+                let byte = byte(value)?;
+                self.emit(&[byte])?;
+            }
+            ":org" => {
+                let token = self.next()?;
+                let address = address(self.value(&token)?)?;
+                if (address as usize) < PROGRAM_START {
+                    return Err(format!(":org 0x{:03X} is below 0x{:03X}", address, PROGRAM_START));
+                }
+                self.here = address;
+            }
+            ":unpack" => {
+                let token = self.next()?;
+                let nibble = self.value(&token)?;
+                if !(0..=0xF).contains(&nibble) {
+                    return Err(format!("{} does not fit into a nibble", token));
+                }
+                let token = self.next()?;
+                let target = address(self.value(&token)?)?;
+                self.instruction(Instruction::LdVxByte { x: 0, byte: (nibble << 4) as u8 | (target >> 8) as u8 })?;
+                self.instruction(Instruction::LdVxByte { x: 1, byte: target as u8 })?;
+            }
+            ":call" => {
+                let target = self.next()?;
+                self.jump_to(&target, 0x2000)?;
+            }
+            ":breakpoint" => {
+                let name = self.next()?;
+                self.breakpoints.insert(self.here, name);
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+
+            "clear" => self.instruction(Instruction::Cls)?,
+            "return" | ";" => self.instruction(Instruction::Ret)?,
+            "jump" => {
+                let target = self.next()?;
+                self.jump_to(&target, 0x1000)?;
+            }
+            "jump0" => {
+                let target = self.next()?;
+                self.jump_to(&target, 0xB000)?;
+            }
+            "native" => {
+                let target = self.next()?;
+                self.jump_to(&target, 0x0000)?;
+            }
+            "sprite" => {
+                let x = self.next_register()?;
+                let y = self.next_register()?;
+                let token = self.next()?;
+                let n = self.value(&token)?;
+                if !(0..=0xF).contains(&n) {
+                    return Err(format!("sprite height {} is larger than 15", token));
+                }
+                self.instruction(Instruction::Drw { x, y, n: n as u8 })?;
+            }
+            "save" => {
+                let x = self.next_register()?;
+                self.instruction(Instruction::LdIVx { x })?;
+            }
+            "load" => {
+                let x = self.next_register()?;
+                self.instruction(Instruction::LdVxI { x })?;
+            }
+            "bcd" => {
+                let x = self.next_register()?;
+                self.instruction(Instruction::LdBVx { x })?;
+            }
+            "delay" | "buzzer" => {
+                self.expect(":=")?;
+                let x = self.next_register()?;
+                let instruction = if word == "delay" { Instruction::LdDtVx { x } } else { Instruction::LdStVx { x } };
+                self.instruction(instruction)?;
+            }
+            "i" => self.index()?,
+
+            "if" => self.conditional()?,
+            "else" => match self.blocks.pop() {
+                Some(Block::If { skip, line }) => {
+                    let jump = self.here;
+                    self.instruction(Instruction::Jmp { addr: 0 })?;
+                    self.patch(skip, self.here);
+                    self.blocks.push(Block::Else { skip: jump, line });
+                }
+                _ => return Err("else without if ... begin".to_string()),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { skip, .. }) | Some(Block::Else { skip, .. }) => self.patch(skip, self.here),
+                _ => return Err("end without if ... begin".to_string()),
+            },
+            "loop" => self.blocks.push(Block::Loop { start: self.here, breaks: Vec::new(), line: self.line }),
+            "while" => {
+                if !self.blocks.iter().any(|block| matches!(*block, Block::Loop { .. })) {
+                    return Err("while outside of a loop".to_string());
+                }
+                //leave the loop unless the condition holds
+                self.condition(true)?;
+                let jump = self.here;
+                self.instruction(Instruction::Jmp { addr: 0 })?;
+                for block in self.blocks.iter_mut().rev() {
+                    if let Block::Loop { ref mut breaks, .. } = *block {
+                        breaks.push(jump);
+                        break;
+                    }
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, breaks, .. }) => {
+                    self.instruction(Instruction::Jmp { addr: start })?;
+                    for jump in breaks {
+                        self.patch(jump, self.here);
+                    }
+                }
+                _ => return Err("again without loop".to_string()),
+            },
+
+            "hires" | "lores" | "scroll-down" | "scroll-up" | "scroll-left" | "scroll-right" | "exit"
+            | "saveflags" | "loadflags" | "plane" | "audio" | "pitch" => {
+                return Err(format!("{} is not a CHIP-8 instruction", word))
+            }
+            _ if word.starts_with(':') => return Err(format!("unsupported directive {}", word)),
+
+            _ => {
+                if let Some(x) = self.register(word) {
+                    return self.assignment(x);
+                }
+                if self.macros.contains_key(word) {
+                    return self.expand(word);
+                }
+                match self.number(word) {
+                    Some(value) => {
+                        let byte = byte(value)?;
+                        self.emit(&[byte])?;
+                    }
+                    None => {
+                        //a bare name calls the subroutine of that name
+                        self.jump_to(word, 0x2000)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `vx := ...`, `vx += ...` and the other register operations.
+    fn assignment(&mut self, x: u8) -> Result<(), String> {
+        let operator = self.next()?;
+        let operand = self.next()?;
+        let y = self.register(&operand);
+
+        let instruction = match (operator.as_str(), y) {
+            (":=", Some(y)) => Instruction::LdVxVy { x, y },
+            (":=", None) => match operand.as_str() {
+                "random" => {
+                    let token = self.next()?;
+                    Instruction::Rnd { x, byte: byte(self.value(&token)?)? }
+                }
+                "delay" => Instruction::LdVxDt { x },
+                "key" => Instruction::LdVxK { x },
+                _ => Instruction::LdVxByte { x, byte: byte(self.value(&operand)?)? },
+            },
+            ("+=", Some(y)) => Instruction::AddVxVy { x, y },
+            ("+=", None) => Instruction::AddVxByte { x, byte: byte(self.value(&operand)?)? },
+            ("-=", Some(y)) => Instruction::SubVxVy { x, y },
+            ("-=", None) => Instruction::AddVxByte { x, byte: byte(self.value(&operand)?)?.wrapping_neg() },
+            ("=-", Some(y)) => Instruction::SubnVxVy { x, y },
+            ("|=", Some(y)) => Instruction::OrVxVy { x, y },
+            ("&=", Some(y)) => Instruction::AndVxVy { x, y },
+            ("^=", Some(y)) => Instruction::XorVxVy { x, y },
+            (">>=", Some(y)) => Instruction::ShrVxVy { x, y },
+            ("<<=", Some(y)) => Instruction::ShlVxVy { x, y },
+            _ => return Err(format!("invalid operation v{:X} {} {}", x, operator, operand)),
+        };
+
+        self.instruction(instruction)
+    }
+
+    /// `i := address`, `i := hex vx` and `i += vx`.
+    fn index(&mut self) -> Result<(), String> {
+        let operator = self.next()?;
+        let operand = self.next()?;
+
+        match (operator.as_str(), operand.as_str()) {
+            (":=", "hex") => {
+                let x = self.next_register()?;
+                self.instruction(Instruction::LdFVx { x })
+            }
+            (":=", "bighex") | (":=", "long") => Err(format!("i := {} is not a CHIP-8 instruction", operand)),
+            (":=", _) => self.jump_to(&operand, 0xA000),
+            ("+=", _) => match self.register(&operand) {
+                Some(x) => self.instruction(Instruction::AddIVx { x }),
+                None => Err(format!("{} is not a register", operand)),
+            },
+            _ => Err(format!("invalid operation i {} {}", operator, operand)),
+        }
+    }
+
+    /// `if <condition> then <statement>` or `if <condition> begin`.
+    fn conditional(&mut self) -> Result<(), String> {
+        let line = self.line;
+        let tokens = self.condition_tokens()?;
+
+        match self.next()?.as_str() {
+            "then" => {
+                push_front_all(&mut self.tokens, tokens);
+                self.condition(false)
+            }
+            "begin" => {
+                push_front_all(&mut self.tokens, tokens);
+                self.condition(true)?;
+                let skip = self.here;
+                self.instruction(Instruction::Jmp { addr: 0 })?;
+                self.blocks.push(Block::If { skip, line });
+                Ok(())
+            }
+            other => Err(format!("expected then or begin, found {}", other)),
+        }
+    }
+
+    /// Takes the tokens of a condition, so `then`/`begin` can be read first.
+    fn condition_tokens(&mut self) -> Result<Vec<Token>, String> {
+        let mut tokens = Vec::new();
+        for _ in 0..2 {
+            tokens.push(self.next_token()?);
+        }
+        if tokens[1].text != "key" && tokens[1].text != "-key" {
+            tokens.push(self.next_token()?);
+        }
+        Ok(tokens)
+    }
+
+    /// Emits code that skips the next instruction if the condition is
+    /// true (`skip_if` set) or false.
+    fn condition(&mut self, skip_if: bool) -> Result<(), String> {
+        let x = self.next_register()?;
+        let operator = self.next()?;
+
+        let instruction = match operator.as_str() {
+            "key" | "-key" => {
+                if (operator == "key") == skip_if {
+                    Instruction::Skp { x }
+                } else {
+                    Instruction::Sknp { x }
+                }
+            }
+            "==" | "!=" => {
+                let operand = self.next()?;
+                let equal = (operator == "==") == skip_if;
+                match self.register(&operand) {
+                    Some(y) if equal => Instruction::SeVxVy { x, y },
+                    Some(y) => Instruction::SneVxVy { x, y },
+                    None => {
+                        let byte = byte(self.value(&operand)?)?;
+                        if equal {
+                            Instruction::SeVxByte { x, byte }
+                        } else {
+                            Instruction::SneVxByte { x, byte }
+                        }
+                    }
+                }
+            }
+            "<" | ">" | "<=" | ">=" => {
+                let operand = self.next()?;
+                let y = self.register(&operand);
+
+                //vf gets the no-borrow flag of either vx - operand or operand - vx
+                let swapped = operator == "<=" || operator == ">";
+                match (swapped, y) {
+                    (false, Some(y)) => {
+                        self.instruction(Instruction::LdVxVy { x: 0xF, y: x })?;
+                        self.instruction(Instruction::SubVxVy { x: 0xF, y })?;
+                    }
+                    (false, None) => {
+                        let byte = byte(self.value(&operand)?)?;
+                        self.instruction(Instruction::LdVxByte { x: 0xF, byte })?;
+                        self.instruction(Instruction::SubnVxVy { x: 0xF, y: x })?;
+                    }
+                    (true, Some(y)) => {
+                        self.instruction(Instruction::LdVxVy { x: 0xF, y })?;
+                        self.instruction(Instruction::SubVxVy { x: 0xF, y: x })?;
+                    }
+                    (true, None) => {
+                        let byte = byte(self.value(&operand)?)?;
+                        self.instruction(Instruction::LdVxByte { x: 0xF, byte })?;
+                        self.instruction(Instruction::SubVxVy { x: 0xF, y: x })?;
+                    }
+                }
+
+                //>= and <= hold when vf is 1, < and > when it is 0
+                let holds_on_flag = operator == ">=" || operator == "<=";
+                let byte = if holds_on_flag == skip_if { 1 } else { 0 };
+                Instruction::SeVxByte { x: 0xF, byte }
+            }
+            _ => return Err(format!("invalid condition operator {}", operator)),
+        };
+
+        self.instruction(instruction)
+    }
+
+    fn define_macro(&mut self) -> Result<(), String> {
+        let name = self.name()?;
+
+        let mut parameters = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token == "{" {
+                break;
+            }
+            parameters.push(token);
+        }
+
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.next_token().map_err(|_| format!("macro {} is missing its closing }}", name))?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => (),
+            }
+            if depth == 0 {
+                break;
+            }
+            body.push(token);
+        }
+
+        self.macros.insert(name, Macro { parameters, body });
+        Ok(())
+    }
+
+    /// Replaces a macro invocation with its body, arguments substituted.
+    fn expand(&mut self, name: &str) -> Result<(), String> {
+        //macros have no conditions, one that expands into itself never stops
+        if self.expansion.iter().any(|macro_name| macro_name == name) {
+            return Err(format!("recursive macro {}", name));
+        }
+
+        let mut arguments = HashMap::new();
+        for parameter in &self.macros[name].parameters {
+            let argument = self.tokens.pop_front().ok_or_else(|| format!("macro {} is missing arguments", name))?;
+            arguments.insert(parameter.clone(), argument.text);
+        }
+
+        //expanded code is reported at the line of the invocation
+        let line = self.line;
+        let mut expansion = self.expansion.clone();
+        expansion.push(name.to_string());
+        let body: Vec<Token> = self.macros[name]
+            .body
+            .iter()
+            .map(|token| Token {
+                text: arguments.get(&token.text).unwrap_or(&token.text).clone(),
+                line,
+                expansion: expansion.clone(),
+            })
+            .collect();
+
+        push_front_all(&mut self.tokens, body);
+        Ok(())
+    }
+
+    /// Evaluates a `{ ... }` expression.
+    fn calc(&mut self) -> Result<f64, String> {
+        self.expect("{")?;
+
+        let mut tokens = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token == "}" {
+                break;
+            }
+            tokens.push(token);
+        }
+
+        let mut position = 0;
+        let value = self.expression(&tokens, &mut position)?;
+        if position < tokens.len() {
+            return Err(format!("unexpected {} in expression", tokens[position]));
+        }
+
+        Ok(value)
+    }
+
+    fn expression(&self, tokens: &[String], position: &mut usize) -> Result<f64, String> {
+        let left = self.term(tokens, position)?;
+
+        match tokens.get(*position) {
+            None => Ok(left),
+            Some(operator) if operator == ")" => Ok(left),
+            Some(operator) => {
+                *position += 1;
+                let right = self.expression(tokens, position)?;
+                binary(operator, left, right)
+            }
+        }
+    }
+
+    fn term(&self, tokens: &[String], position: &mut usize) -> Result<f64, String> {
+        let token = tokens.get(*position).ok_or("incomplete expression")?;
+        *position += 1;
+
+        match token.as_str() {
+            "(" => {
+                let value = self.expression(tokens, position)?;
+                if tokens.get(*position).map(|token| token.as_str()) != Some(")") {
+                    return Err("missing ) in expression".to_string());
+                }
+                *position += 1;
+                Ok(value)
+            }
+            "-" => Ok(-self.term(tokens, position)?),
+            "~" => Ok(!(self.term(tokens, position)? as i64) as f64),
+            "!" => Ok(if self.term(tokens, position)? == 0.0 { 1.0 } else { 0.0 }),
+            "abs" => Ok(self.term(tokens, position)?.abs()),
+            "sqrt" => Ok(self.term(tokens, position)?.sqrt()),
+            "floor" => Ok(self.term(tokens, position)?.floor()),
+            "ceil" => Ok(self.term(tokens, position)?.ceil()),
+            "HERE" => Ok(self.here as f64),
+            _ => match self.constants.get(token.as_str()) {
+                Some(&value) => Ok(value),
+                None => self.value(token).map(|value| value as f64),
+            },
+        }
+    }
+
+    fn constant(&mut self, name: String, value: f64) -> Result<(), String> {
+        if self.labels.contains_key(&name) || self.aliases.contains_key(&name) {
+            return Err(format!("{} is already defined", name));
+        }
+        self.constants.insert(name, value);
+        Ok(())
+    }
+
+    /// A number, constant or defined label.
+    fn value(&self, token: &str) -> Result<i64, String> {
+        if let Some(value) = self.number(token) {
+            return Ok(value);
+        }
+        if let Some(&value) = self.constants.get(token) {
+            return Ok(value.floor() as i64);
+        }
+        if let Some(&address) = self.labels.get(token) {
+            return Ok(address as i64);
+        }
+        Err(format!("undefined name {}", token))
+    }
+
+    fn number(&self, token: &str) -> Option<i64> {
+        let (negative, digits) = match token.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, token),
+        };
+
+        let value = if let Some(hex) = digits.strip_prefix("0x") {
+            i64::from_str_radix(hex, 16).ok()?
+        } else if let Some(binary) = digits.strip_prefix("0b") {
+            i64::from_str_radix(binary, 2).ok()?
+        } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+            digits.parse().ok()?
+        } else {
+            return None;
+        };
+
+        Some(if negative { -value } else { value })
+    }
+
+    fn register(&self, token: &str) -> Option<u8> {
+        if let Some(&register) = self.aliases.get(token) {
+            return Some(register);
+        }
+        if token.len() == 2 && (token.starts_with('v') || token.starts_with('V')) {
+            u8::from_str_radix(&token[1..], 16).ok()
+        } else {
+            None
+        }
+    }
+
+    fn next_register(&mut self) -> Result<u8, String> {
+        let token = self.next()?;
+        self.register(&token).ok_or_else(|| format!("{} is not a register", token))
+    }
+
+    /// Emits `opcode | address`, patched later if the label is still unknown.
+    fn jump_to(&mut self, target: &str, opcode: u16) -> Result<(), String> {
+        let address = match self.value(target) {
+            Ok(value) => address(value)?,
+            Err(_) if is_name(target) => {
+                self.fixups.push(Fixup { address: self.here, label: target.to_string(), line: self.line });
+                0
+            }
+            Err(err) => return Err(err),
+        };
+
+        let opcode = opcode | address;
+        self.emit(&[(opcode >> 8) as u8, opcode as u8])
+    }
+
+    fn instruction(&mut self, instruction: Instruction) -> Result<(), String> {
+        let opcode = instruction.encode();
+        self.emit(&[(opcode >> 8) as u8, opcode as u8])
+    }
+
+    fn emit(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let start = self.here as usize;
+        let end = start + bytes.len();
+        if end > MEMORY_SIZE {
+            return Err("program does not fit into memory".to_string());
+        }
+
+        if self.rom.len() < end - PROGRAM_START {
+            self.rom.resize(end - PROGRAM_START, 0);
+        }
+        self.rom[start - PROGRAM_START..end - PROGRAM_START].copy_from_slice(bytes);
+        self.lines.insert(self.here, self.line);
+        self.here = end as u16;
+
+        Ok(())
+    }
+
+    /// Points the jump at `jump` to `target`.
+    fn patch(&mut self, jump: u16, target: u16) {
+        let offset = jump as usize - PROGRAM_START;
+        self.rom[offset] = (self.rom[offset] & 0xF0) | (target >> 8) as u8;
+        self.rom[offset + 1] = target as u8;
+    }
+
+    fn finish(mut self) -> Result<Program, OctoError> {
+        if let Some(block) = self.blocks.last() {
+            let (line, message) = match *block {
+                Block::If { line, .. } | Block::Else { line, .. } => (line, "if ... begin without end"),
+                Block::Loop { line, .. } => (line, "loop without again"),
+            };
+            return Err(OctoError { line, message: message.to_string() });
+        }
+
+        for fixup in &self.fixups {
+            let target = match self.labels.get(&fixup.label) {
+                Some(&target) => target,
+                None => {
+                    return Err(OctoError { line: fixup.line, message: format!("undefined name {}", fixup.label) })
+                }
+            };
+            let offset = fixup.address as usize - PROGRAM_START;
+            self.rom[offset] |= (target >> 8) as u8;
+            self.rom[offset + 1] |= target as u8;
+        }
+
+        Ok(Program {
+            rom: self.rom,
+            labels: self.labels.into_iter().collect(),
+            lines: self.lines,
+            breakpoints: self.breakpoints,
+        })
+    }
+
+    fn next_token(&mut self) -> Result<Token, String> {
+        self.tokens.pop_front().ok_or_else(|| "unexpected end of program".to_string())
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        self.next_token().map(|token| token.text)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        let token = self.next()?;
+        if token == expected {
+            Ok(())
+        } else {
+            Err(format!("expected {}, found {}", expected, token))
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let name = self.next()?;
+        if !is_name(&name) || self.register(&name).is_some() {
+            return Err(format!("invalid name {}", name));
+        }
+        Ok(name)
+    }
+}
+
+fn push_front_all(tokens: &mut VecDeque<Token>, items: Vec<Token>) {
+    for item in items.into_iter().rev() {
+        tokens.push_front(item);
+    }
+}
+
+fn binary(operator: &str, left: f64, right: f64) -> Result<f64, String> {
+    let (a, b) = (left as i64, right as i64);
+    let truth = |value: bool| if value { 1.0 } else { 0.0 };
+
+    let value = match operator {
+        "+" => left + right,
+        "-" => left - right,
+        "*" => left * right,
+        "/" => left / right,
+        "%" => left % right,
+        "pow" => left.powf(right),
+        "min" => left.min(right),
+        "max" => left.max(right),
+        "&" => (a & b) as f64,
+        "|" => (a | b) as f64,
+        "^" => (a ^ b) as f64,
+        "<<" | ">>" => {
+            let shift = |a: i64, b: u32| if operator == "<<" { a.checked_shl(b) } else { a.checked_shr(b) };
+            let shifted = u32::try_from(b).ok().and_then(|b| shift(a, b));
+            shifted.ok_or_else(|| "shift out of range".to_string())? as f64
+        }
+        "<" => truth(left < right),
+        ">" => truth(left > right),
+        "<=" => truth(left <= right),
+        ">=" => truth(left >= right),
+        "==" => truth(left == right),
+        "!=" => truth(left != right),
+        _ => return Err(format!("unknown operator {}", operator)),
+    };
+
+    Ok(value)
+}
+
+fn byte(value: i64) -> Result<u8, String> {
+    match value {
+        -128..=-1 => Ok((value + 256) as u8),
+        0..=255 => Ok(value as u8),
+        _ => Err(format!("{} does not fit into a byte", value)),
+    }
+}
+
+fn address(value: i64) -> Result<u16, String> {
+    if (0..=0xFFF).contains(&value) {
+        Ok(value as u16)
+    } else {
+        Err(format!("address {} is out of range", value))
+    }
+}
+
+fn is_name(token: &str) -> bool {
+    token.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && token.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Splits the source at whitespace, `#` starts a comment.
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+
+    for (index, line) in source.lines().enumerate() {
+        let code = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+        for word in code.split_whitespace() {
+            tokens.push(Token { text: word.to_string(), line: index + 1, expansion: Vec::new() });
+        }
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(source: &str) -> Vec<u16> {
+        let rom = compile(source).unwrap().rom;
+        rom.chunks(2).map(|word| u16::from(word[0]) << 8 | u16::from(word[1])).collect()
+    }
+
+    fn error(source: &str) -> OctoError {
+        compile(source).unwrap_err()
+    }
+
+    #[test]
+    fn if_then_skips_over_the_statement() {
+        assert_eq!(words("if v0 == 5 then v1 := 2"), [0x4005, 0x6102]);
+        assert_eq!(words("if v0 key then v1 := 2"), [0xE0A1, 0x6102]);
+    }
+
+    #[test]
+    fn if_begin_else_end_jumps_around_the_branches() {
+        let source = "
+            if v0 != v1 begin
+                v2 := 1
+            else
+                v2 := 2
+            end";
+        assert_eq!(words(source), [0x9010, 0x1208, 0x6201, 0x120A, 0x6202]);
+    }
+
+    #[test]
+    fn comparisons_go_through_vf() {
+        assert_eq!(words("if v0 < 5 then v1 := 1"), [0x6F05, 0x8F07, 0x3F01, 0x6101]);
+    }
+
+    #[test]
+    fn loop_while_again_breaks_past_the_loop() {
+        assert_eq!(words("loop v0 += 1 while v0 != 10 again"), [0x7001, 0x400A, 0x1208, 0x1200]);
+    }
+
+    #[test]
+    fn calc_evaluates_right_to_left() {
+        assert_eq!(words(":calc x { 3 + 2 * 4 } v0 := x"), [0x600B]);
+        assert_eq!(words(":calc x { ( 3 + 2 ) * 4 } v0 := x"), [0x6014]);
+        assert_eq!(words(":calc x { 1 << 4 } :calc y { 256 >> 2 } v0 := x v1 := y"), [0x6010, 0x6140]);
+    }
+
+    #[test]
+    fn calc_rejects_shifts_out_of_range() {
+        assert_eq!(error("\n:calc x { 1 << 64 }"), OctoError { line: 2, message: "shift out of range".to_string() });
+        assert_eq!(error(":calc x { 1 << -1 }").message, "shift out of range");
+        assert_eq!(error(":calc x { 1 >> 99 }").message, "shift out of range");
+    }
+
+    #[test]
+    fn labels_main_and_source_lines() {
+        let program = compile(": sub\nreturn\n: main\nsub\nloop again").unwrap();
+        assert_eq!(program.labels["sub"], 0x202);
+        assert_eq!(program.labels["main"], 0x204);
+        assert_eq!(&program.rom[..2], [0x12, 0x04]);
+        assert_eq!(program.line_at(0x204), Some(4));
+        assert_eq!(program.locate(0x206), "line 5, main+2");
+    }
+
+    #[test]
+    fn macros_expand_with_their_arguments() {
+        let source = ":macro set reg value { reg := value }\n:macro twice reg { set reg 1 set reg 2 }\ntwice v3";
+        assert_eq!(words(source), [0x6301, 0x6302]);
+    }
+
+    #[test]
+    fn recursive_macros_are_rejected() {
+        assert_eq!(error(":macro m { m }\nm").message, "recursive macro m");
+        assert_eq!(error(":macro a { b }\n:macro b { v0 += 1 a }\n\na"), OctoError {
+            line: 4,
+            message: "recursive macro a".to_string(),
+        });
+    }
+
+    #[test]
+    fn errors_carry_the_line_number() {
+        assert_eq!(error("clear\nloop\nv0 := 1").line, 2);
+        assert_eq!(error("jump nowhere").message, "undefined name nowhere");
+        assert_eq!(error("v0 := 256").message, "256 does not fit into a byte");
+        assert_eq!(error("hires").message, "hires is not a CHIP-8 instruction");
+    }
+}
@@ -0,0 +1,150 @@
+/// Toggles for instructions that behave differently between CHIP-8
+/// interpreters.
+///
+/// `Quirks::default()` keeps the behaviour this emulator always had, the
+/// presets match the interpreters most ROMs were written for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Quirks {
+    /// 8XY6 and 8XYE shift VY into VX instead of shifting VX in place.
+    pub shift_uses_vy: bool,
+    /// Where FX55 and FX65 leave I.
+    pub load_store_index: LoadStoreIndex,
+    /// BNNN jumps to XNN + VX (BXNN) instead of NNN + V0.
+    pub jump_uses_vx: bool,
+    /// Sprites are cut off at the screen edges instead of wrapping around.
+    pub clip_sprites: bool,
+    /// 8XY1, 8XY2 and 8XY3 reset VF to zero.
+    pub logic_resets_vf: bool,
+    /// DXYN waits for the next frame, so at most one sprite is drawn per frame.
+    pub display_wait: bool,
+    /// FX0A waits for the key to be released before storing it.
+    pub key_wait_release: bool,
+}
+
+/// Where FX55 and FX65 leave I after copying V0 to VX.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LoadStoreIndex {
+    /// I is not changed, as on SUPER-CHIP.
+    #[default]
+    Unchanged,
+    /// I + X, pointing at the last register copied, as on CHIP-48.
+    PlusX,
+    /// I + X + 1, pointing past the last register copied, as on the VIP.
+    PlusXPlusOne,
+}
+
+/// Names of the quirks, as used in movie files and on the command line.
+/// `load_store_increments_i` selects `LoadStoreIndex::PlusXPlusOne` and
+/// `load_store_increments_i_by_x` selects `LoadStoreIndex::PlusX`.
+pub const QUIRK_NAMES: [&str; 8] = [
+    "shift_uses_vy",
+    "load_store_increments_i",
+    "load_store_increments_i_by_x",
+    "jump_uses_vx",
+    "clip_sprites",
+    "logic_resets_vf",
+    "display_wait",
+    "key_wait_release",
+];
+
+impl Quirks {
+    /// The original interpreter on the RCA COSMAC VIP.
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_index: LoadStoreIndex::PlusXPlusOne,
+            jump_uses_vx: false,
+            clip_sprites: true,
+            logic_resets_vf: true,
+            display_wait: true,
+            key_wait_release: true,
+        }
+    }
+
+    /// CHIP-48 on the HP-48 calculators.
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_index: LoadStoreIndex::PlusX,
+            jump_uses_vx: true,
+            clip_sprites: true,
+            logic_resets_vf: false,
+            display_wait: false,
+            key_wait_release: false,
+        }
+    }
+
+    /// SUPER-CHIP 1.1, the behaviour most later interpreters copied.
+    pub fn super_chip() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_index: LoadStoreIndex::Unchanged,
+            jump_uses_vx: true,
+            clip_sprites: true,
+            logic_resets_vf: false,
+            display_wait: false,
+            key_wait_release: false,
+        }
+    }
+
+    /// Looks up a preset by name: `vip`, `chip48`, `schip` or `legacy`.
+    pub fn preset(name: &str) -> Option<Quirks> {
+        match name {
+            "vip" | "cosmac_vip" => Some(Quirks::cosmac_vip()),
+            "chip48" => Some(Quirks::chip48()),
+            "schip" | "super_chip" => Some(Quirks::super_chip()),
+            "legacy" => Some(Quirks::default()),
+            _ => None,
+        }
+    }
+
+    /// Names of the quirks that are turned on.
+    pub fn enabled(&self) -> Vec<&'static str> {
+        QUIRK_NAMES.iter().cloned().filter(|name| self.get(name) == Some(true)).collect()
+    }
+
+    /// Whether a quirk is on, None if there is no such quirk.
+    pub fn get(&self, name: &str) -> Option<bool> {
+        let enabled = match name {
+            "shift_uses_vy" => self.shift_uses_vy,
+            "load_store_increments_i" => self.load_store_index == LoadStoreIndex::PlusXPlusOne,
+            "load_store_increments_i_by_x" => self.load_store_index == LoadStoreIndex::PlusX,
+            "jump_uses_vx" => self.jump_uses_vx,
+            "clip_sprites" => self.clip_sprites,
+            "logic_resets_vf" => self.logic_resets_vf,
+            "display_wait" => self.display_wait,
+            "key_wait_release" => self.key_wait_release,
+            _ => return None,
+        };
+        Some(enabled)
+    }
+
+    /// Turns a quirk on or off by name, false if there is no such quirk.
+    ///
+    /// The two `load_store_increments_i` quirks exclude each other, turning
+    /// one on turns the other off.
+    pub fn set(&mut self, name: &str, enabled: bool) -> bool {
+        let flag = match name {
+            "load_store_increments_i" => return self.set_load_store_index(LoadStoreIndex::PlusXPlusOne, enabled),
+            "load_store_increments_i_by_x" => return self.set_load_store_index(LoadStoreIndex::PlusX, enabled),
+            "shift_uses_vy" => &mut self.shift_uses_vy,
+            "jump_uses_vx" => &mut self.jump_uses_vx,
+            "clip_sprites" => &mut self.clip_sprites,
+            "logic_resets_vf" => &mut self.logic_resets_vf,
+            "display_wait" => &mut self.display_wait,
+            "key_wait_release" => &mut self.key_wait_release,
+            _ => return false,
+        };
+        *flag = enabled;
+        true
+    }
+
+    fn set_load_store_index(&mut self, index: LoadStoreIndex, enabled: bool) -> bool {
+        if enabled {
+            self.load_store_index = index;
+        } else if self.load_store_index == index {
+            self.load_store_index = LoadStoreIndex::Unchanged;
+        }
+        true
+    }
+}
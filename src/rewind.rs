@@ -0,0 +1,312 @@
+use std::collections::VecDeque;
+
+use chip8::Chip8;
+use clock::TIMER_HZ;
+use error::StateError;
+
+/// Frames between two full snapshots, the ones in between are deltas.
+pub const DEFAULT_KEYFRAME_INTERVAL: usize = 60;
+
+/// Differences closer than this are merged into one run, a run costs more
+/// than a few unchanged bytes.
+const MERGE_GAP: usize = 8;
+
+/// A bounded history of per-frame save states to step the emulation
+/// backwards in time.
+///
+/// Call `record` after every frame. Every `keyframe_interval` frames a full
+/// state is kept, the frames in between only store the bytes that differ
+/// from it. Once more than `capacity` frames are recorded, the oldest
+/// keyframe and its deltas are dropped.
+pub struct Rewind {
+    segments: VecDeque<Segment>,
+    frames: usize,
+    capacity: usize,
+    keyframe_interval: usize,
+}
+
+struct Segment {
+    keyframe: Vec<u8>,
+    deltas: Vec<Delta>,
+}
+
+/// Runs of bytes that differ from the segment's keyframe.
+type Delta = Vec<(usize, Vec<u8>)>;
+
+impl Rewind {
+    /// Keeps about the given number of seconds of 60 Hz frames.
+    pub fn new(seconds: u32) -> Rewind {
+        Rewind::with_capacity(seconds as usize * TIMER_HZ as usize)
+    }
+
+    pub fn with_capacity(frames: usize) -> Rewind {
+        Rewind {
+            segments: VecDeque::new(),
+            frames: 0,
+            capacity: frames.max(1),
+            keyframe_interval: DEFAULT_KEYFRAME_INTERVAL,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn set_capacity(&mut self, frames: usize) {
+        self.capacity = frames.max(1);
+        self.evict();
+    }
+
+    /// Trades memory for restore speed, 1 stores every frame in full.
+    pub fn set_keyframe_interval(&mut self, frames: usize) {
+        self.keyframe_interval = frames.max(1);
+    }
+
+    /// Number of recorded frames, including the latest one.
+    pub fn len(&self) -> usize {
+        self.frames
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.segments.clear();
+        self.frames = 0;
+    }
+
+    /// Approximate number of bytes held by the snapshots.
+    pub fn memory_usage(&self) -> usize {
+        self.segments
+            .iter()
+            .map(|segment| {
+                let deltas: usize = segment
+                    .deltas
+                    .iter()
+                    .flat_map(|delta| delta.iter())
+                    .map(|(_, run)| run.len() + 2 * std::mem::size_of::<usize>())
+                    .sum();
+                segment.keyframe.len() + deltas
+            })
+            .sum()
+    }
+
+    /// Snapshots the machine, call once per frame.
+    pub fn record(&mut self, chip8: &Chip8) {
+        let state = chip8.save_state();
+
+        let delta = match self.segments.back() {
+            Some(segment) if segment.deltas.len() + 1 < self.keyframe_interval
+                && segment.keyframe.len() == state.len() => Some(diff(&segment.keyframe, &state)),
+            _ => None,
+        };
+
+        match delta {
+            Some(delta) => self.segments.back_mut().unwrap().deltas.push(delta),
+            None => self.segments.push_back(Segment { keyframe: state, deltas: Vec::new() }),
+        }
+        self.frames += 1;
+
+        self.evict();
+    }
+
+    /// Restores the state from `frames` frames before the latest one and
+    /// forgets everything newer. Returns how many frames were rewound,
+    /// which is less than asked for if the history is shorter.
+    ///
+    /// Fails, keeping the machine and the history as they are, if the
+    /// random source of the machine was swapped for one that can not be
+    /// restored since recording.
+    pub fn rewind(&mut self, chip8: &mut Chip8, frames: usize) -> Result<usize, StateError> {
+        let frames = frames.min(self.frames.saturating_sub(1));
+
+        //find the segment and delta of the target frame, counting from the newest
+        let mut skipped = frames;
+        let mut kept_segments = self.segments.len();
+        for segment in self.segments.iter().rev() {
+            if skipped <= segment.deltas.len() {
+                break;
+            }
+            skipped -= segment.deltas.len() + 1;
+            kept_segments -= 1;
+        }
+
+        let segment = match kept_segments.checked_sub(1).and_then(|index| self.segments.get(index)) {
+            Some(segment) => segment,
+            None => return Ok(0),
+        };
+        let kept_deltas = segment.deltas.len() - skipped;
+
+        let mut state = segment.keyframe.clone();
+        if let Some(delta) = kept_deltas.checked_sub(1).map(|last| &segment.deltas[last]) {
+            for &(offset, ref run) in delta {
+                state[offset..offset + run.len()].copy_from_slice(run);
+            }
+        }
+        chip8.load_state(&state)?;
+
+        self.segments.truncate(kept_segments);
+        if let Some(segment) = self.segments.back_mut() {
+            segment.deltas.truncate(kept_deltas);
+        }
+        self.frames -= frames;
+
+        Ok(frames)
+    }
+
+    /// Goes back a single frame, for hold-to-rewind. Returns false once
+    /// the oldest recorded frame is reached.
+    pub fn step_back(&mut self, chip8: &mut Chip8) -> Result<bool, StateError> {
+        Ok(self.rewind(chip8, 1)? == 1)
+    }
+
+    fn evict(&mut self) {
+        while let Some(oldest) = self.segments.front().map(|segment| segment.deltas.len() + 1) {
+            if self.segments.len() == 1 || self.frames - oldest < self.capacity {
+                break;
+            }
+            self.segments.pop_front();
+            self.frames -= oldest;
+        }
+    }
+}
+
+fn diff(keyframe: &[u8], state: &[u8]) -> Delta {
+    let mut runs: Delta = Vec::new();
+
+    let mut offset = 0;
+    while offset < state.len() {
+        if keyframe[offset] == state[offset] {
+            offset += 1;
+            continue;
+        }
+
+        let start = offset;
+        let mut end = offset + 1;
+        let mut same = 0;
+        while end < state.len() && same < MERGE_GAP {
+            if keyframe[end] == state[end] {
+                same += 1;
+            } else {
+                same = 0;
+            }
+            end += 1;
+        }
+        let end = end - same;
+
+        runs.push((start, state[start..end].to_vec()));
+        offset = end;
+    }
+
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use random::RandomSource;
+
+    /// Counts V0 up and draws a random V1 in a loop, so every frame differs.
+    const ROM: &[u8] = &[0x70, 0x01, 0xC1, 0xFF, 0x12, 0x00];
+
+    /// Runs and records `frames` frames, returns the save state of every
+    /// frame next to the history.
+    fn record(frames: usize, rewind: &mut Rewind) -> (Chip8, Vec<Vec<u8>>) {
+        let mut chip8 = Chip8::from_rom(ROM).unwrap();
+        let mut states = Vec::new();
+        for _ in 0..frames {
+            chip8.run_frame().unwrap();
+            rewind.record(&chip8);
+            states.push(chip8.save_state());
+        }
+        (chip8, states)
+    }
+
+    fn history(capacity: usize, keyframe_interval: usize) -> Rewind {
+        let mut rewind = Rewind::with_capacity(capacity);
+        rewind.set_keyframe_interval(keyframe_interval);
+        rewind
+    }
+
+    #[test]
+    fn restores_the_state_of_every_recorded_frame() {
+        for &interval in &[1, 4, DEFAULT_KEYFRAME_INTERVAL] {
+            for frames in 0..20 {
+                let mut rewind = history(100, interval);
+                let (mut chip8, states) = record(20, &mut rewind);
+
+                assert_eq!(rewind.rewind(&mut chip8, frames), Ok(frames));
+                assert_eq!(chip8.save_state(), states[19 - frames], "{} back, keyframes every {}", frames, interval);
+                assert_eq!(rewind.len(), 20 - frames);
+            }
+        }
+    }
+
+    #[test]
+    fn rewinds_step_by_step_and_records_again() {
+        let mut rewind = history(100, 4);
+        let (mut chip8, states) = record(10, &mut rewind);
+
+        for frame in (0..9).rev() {
+            assert_eq!(rewind.step_back(&mut chip8), Ok(true));
+            assert_eq!(chip8.save_state(), states[frame]);
+        }
+        assert_eq!(rewind.step_back(&mut chip8), Ok(false));
+        assert_eq!(chip8.save_state(), states[0]);
+
+        //the machine goes on exactly as the first time
+        for state in &states[1..6] {
+            chip8.run_frame().unwrap();
+            rewind.record(&chip8);
+            assert_eq!(&chip8.save_state(), state);
+        }
+        assert_eq!(rewind.len(), 6);
+        assert_eq!(rewind.rewind(&mut chip8, 2), Ok(2));
+        assert_eq!(chip8.save_state(), states[3]);
+    }
+
+    #[test]
+    fn drops_the_oldest_segments_beyond_the_capacity() {
+        let mut rewind = history(10, 4);
+        let (mut chip8, states) = record(25, &mut rewind);
+
+        //whole segments go, so a few more frames than the capacity may stay
+        let kept = rewind.len();
+        assert!((10..10 + 4).contains(&kept), "{} frames kept", kept);
+
+        assert_eq!(rewind.rewind(&mut chip8, 100), Ok(kept - 1));
+        assert_eq!(chip8.save_state(), states[25 - kept]);
+        assert_eq!(rewind.len(), 1);
+    }
+
+    #[test]
+    fn empty_history_rewinds_nothing() {
+        let mut rewind = Rewind::new(1);
+        let mut chip8 = Chip8::from_rom(ROM).unwrap();
+        let state = chip8.save_state();
+
+        assert_eq!(rewind.rewind(&mut chip8, 5), Ok(0));
+        assert_eq!(chip8.save_state(), state);
+    }
+
+    struct Constant;
+
+    impl RandomSource for Constant {
+        fn next_byte(&mut self) -> u8 {
+            4
+        }
+    }
+
+    #[test]
+    fn fails_without_changes_after_the_random_source_was_swapped() {
+        let mut rewind = history(100, 4);
+        let (mut chip8, _) = record(10, &mut rewind);
+        chip8.set_random_source(Box::new(Constant));
+        let state = chip8.save_state();
+
+        assert_eq!(rewind.rewind(&mut chip8, 3), Err(StateError::RandomSourceMismatch));
+        assert_eq!(chip8.save_state(), state);
+        assert_eq!(rewind.len(), 10);
+    }
+}
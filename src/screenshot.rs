@@ -0,0 +1,144 @@
+use std::io::{self, Write};
+
+use chip8::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use state::crc32;
+
+/// Writes the display as a plain PBM image, lit pixels are black.
+///
+/// The format is text, so two dumps can be compared with `diff`.
+pub fn write_pbm<W: Write>(gfx: &[u8], mut out: W) -> io::Result<()> {
+    writeln!(out, "P1")?;
+    writeln!(out, "{} {}", SCREEN_WIDTH, SCREEN_HEIGHT)?;
+
+    for row in gfx.chunks(SCREEN_WIDTH) {
+        let pixels: Vec<&str> = row.iter().map(|&pixel| if pixel != 0 { "1" } else { "0" }).collect();
+        writeln!(out, "{}", pixels.join(" "))?;
+    }
+
+    Ok(())
+}
+
+/// Writes the display as a grayscale PNG with every pixel blown up to a
+/// `scale` x `scale` square, lit pixels are white on black.
+pub fn write_png<W: Write>(gfx: &[u8], scale: usize, mut out: W) -> io::Result<()> {
+    let scale = scale.max(1);
+    let width = SCREEN_WIDTH * scale;
+    let height = SCREEN_HEIGHT * scale;
+
+    //every scanline starts with filter type 0, no filtering
+    let mut pixels = Vec::with_capacity((width + 1) * height);
+    for row in gfx.chunks(SCREEN_WIDTH) {
+        let mut line = Vec::with_capacity(width + 1);
+        line.push(0);
+        for &pixel in row {
+            let value = if pixel != 0 { 0xFF } else { 0x00 };
+            line.extend((0..scale).map(|_| value));
+        }
+        for _ in 0..scale {
+            pixels.extend_from_slice(&line);
+        }
+    }
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    //8 bit grayscale, deflate, adaptive filtering, no interlace
+    header.extend_from_slice(&[8, 0, 0, 0, 0]);
+
+    out.write_all(b"\x89PNG\r\n\x1a\n")?;
+    write_chunk(&mut out, b"IHDR", &header)?;
+    write_chunk(&mut out, b"IDAT", &zlib_stored(&pixels))?;
+    write_chunk(&mut out, b"IEND", &[])
+}
+
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    let mut checked = kind.to_vec();
+    checked.extend_from_slice(data);
+
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(&checked)?;
+    out.write_all(&crc32(&checked).to_be_bytes())
+}
+
+/// Wraps `data` into a zlib stream of uncompressed deflate blocks, the
+/// images are small enough to not bother compressing.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        stream.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let length = block.len() as u16;
+        stream.push(last as u8);
+        stream.extend_from_slice(&length.to_le_bytes());
+        stream.extend_from_slice(&(!length).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    stream.extend_from_slice(&(b << 16 | a).to_be_bytes());
+
+    stream
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The chunks of a PNG as (type, data), checking each CRC on the way.
+    fn chunks(png: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            let checked = &rest[4..8 + length];
+            let crc = &rest[8 + length..12 + length];
+            assert_eq!(crc, &crc32(checked).to_be_bytes()[..]);
+
+            chunks.push((String::from_utf8(checked[..4].to_vec()).unwrap(), checked[4..].to_vec()));
+            rest = &rest[12 + length..];
+        }
+        chunks
+    }
+
+    #[test]
+    fn writes_a_valid_png() {
+        let mut gfx = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        gfx[0] = 1;
+        let mut png = Vec::new();
+        write_png(&gfx, 3, &mut png).unwrap();
+
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        let chunks = chunks(&png);
+        let kinds: Vec<&str> = chunks.iter().map(|chunk| chunk.0.as_str()).collect();
+        assert_eq!(kinds, ["IHDR", "IDAT", "IEND"]);
+
+        let header = &chunks[0].1;
+        assert_eq!(&header[..8], &[0, 0, 0, 192, 0, 0, 0, 96]);
+        assert_eq!(&header[8..], &[8, 0, 0, 0, 0]);
+
+        //one filter byte and 192 pixels per row, the first three lit
+        let idat = &chunks[1].1;
+        assert_eq!(&idat[..2], &[0x78, 0x01]);
+        assert_eq!(&idat[7..12], &[0, 0xFF, 0xFF, 0xFF, 0]);
+    }
+
+    #[test]
+    fn writes_a_plain_pbm() {
+        let mut pbm = Vec::new();
+        write_pbm(&vec![1; SCREEN_WIDTH * SCREEN_HEIGHT], &mut pbm).unwrap();
+
+        let text = String::from_utf8(pbm).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[..2], ["P1", "64 32"]);
+        assert_eq!(lines.len(), 2 + SCREEN_HEIGHT);
+        assert!(lines[2].split(' ').all(|pixel| pixel == "1"));
+    }
+}
@@ -0,0 +1,480 @@
+use chip8::{Chip8, KeyWait, MEMORY_SIZE};
+use clock::TIMER_HZ;
+use error::StateError;
+
+const MAGIC: &[u8; 4] = b"C8ST";
+
+/// Format version written by `save_state`.
+pub const STATE_VERSION: u16 = 3;
+
+const HEADER_SIZE: usize = 10;
+const CHECKSUM_SIZE: usize = 4;
+
+// Save states are laid out big endian as
+//
+// ```text
+// "C8ST"  version: u16  payload length: u32
+// memory[4096]  V0..VF  I: u16  PC: u16  stack: 16 x u16  SP: u8
+// DT: u8  ST: u8  gfx: 256 bytes, one bit per pixel  keys: u16 bitmask
+// key wait: u8 state, u8 key  opcode: u16
+// random source state: u8 present, u64 (since version 2)
+// seed: u64  clock remainder: u8  instructions left in the frame: u32
+// frame: u64  cycles: u64 (since version 3)
+// CRC-32 of everything before it: u32
+// ```
+//
+// Settings like quirks, speed and the audio sink are not part of a state.
+impl Chip8 {
+    /// Snapshots the whole machine into a compact binary save state.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(MEMORY_SIZE + 400);
+
+        payload.extend_from_slice(&self.memory);
+        payload.extend_from_slice(&self.registers);
+        push_u16(&mut payload, self.indexing);
+        push_u16(&mut payload, self.program_counter as u16);
+        for &address in &self.stack {
+            push_u16(&mut payload, address);
+        }
+        payload.push(self.stack_pointer as u8);
+        payload.push(self.delay_timer);
+        payload.push(self.sound_timer);
+
+        for pixels in self.gfx.chunks(8) {
+            let packed = pixels.iter().fold(0, |byte, &pixel| byte << 1 | (pixel & 1));
+            payload.push(packed);
+        }
+
+        push_u16(&mut payload, self.keys);
+
+        let (wait, key) = match self.key_wait {
+            KeyWait::Idle => (0, 0),
+            KeyWait::Press => (1, 0),
+            KeyWait::Release(key) => (2, key),
+            KeyWait::Done(key) => (3, key),
+        };
+        payload.push(wait);
+        payload.push(key);
+        push_u16(&mut payload, self.opcode);
+
+        let random = self.rng.state();
+        payload.push(random.is_some() as u8);
+        push_u32(&mut payload, (random.unwrap_or(0) >> 32) as u32);
+        push_u32(&mut payload, random.unwrap_or(0) as u32);
+
+        push_u64(&mut payload, self.seed);
+        payload.push(self.clock.remainder() as u8);
+        push_u32(&mut payload, self.frame_budget);
+        push_u64(&mut payload, self.frame);
+        push_u64(&mut payload, self.cycles);
+
+        let mut state = Vec::with_capacity(HEADER_SIZE + payload.len() + CHECKSUM_SIZE);
+        state.extend_from_slice(MAGIC);
+        push_u16(&mut state, STATE_VERSION);
+        push_u32(&mut state, payload.len() as u32);
+        state.extend_from_slice(&payload);
+
+        let checksum = crc32(&state);
+        push_u32(&mut state, checksum);
+
+        state
+    }
+
+    /// Restores a state written by `save_state`.
+    ///
+    /// The state is fully validated first, on error the machine is left
+    /// untouched.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        if state.len() < MAGIC.len() || &state[..MAGIC.len()] != MAGIC {
+            return Err(StateError::NotAState);
+        }
+
+        let mut header = Reader::new(&state[MAGIC.len()..]);
+        let version = header.u16()?;
+        if version == 0 || version > STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let length = header.u32()? as usize;
+
+        let end = HEADER_SIZE + length;
+        if state.len() < end + CHECKSUM_SIZE {
+            return Err(StateError::Truncated);
+        }
+        if state.len() > end + CHECKSUM_SIZE {
+            return Err(StateError::TrailingBytes(state.len() - end - CHECKSUM_SIZE));
+        }
+        let expected = Reader::new(&state[end..]).u32()?;
+        let actual = crc32(&state[..end]);
+        if expected != actual {
+            return Err(StateError::ChecksumMismatch { expected, actual });
+        }
+
+        let mut reader = Reader::new(&state[HEADER_SIZE..end]);
+
+        let mut memory = [0; MEMORY_SIZE];
+        memory.copy_from_slice(reader.bytes(MEMORY_SIZE)?);
+        let mut registers = [0; 16];
+        registers.copy_from_slice(reader.bytes(16)?);
+        let indexing = reader.u16()?;
+
+        let program_counter = reader.u16()? as usize;
+        if program_counter >= MEMORY_SIZE {
+            return Err(StateError::InvalidField("program counter"));
+        }
+
+        let mut stack = [0; 16];
+        for address in &mut stack {
+            *address = reader.u16()?;
+        }
+        let stack_pointer = reader.u8()? as usize;
+        if stack_pointer > stack.len() {
+            return Err(StateError::InvalidField("stack pointer"));
+        }
+
+        let delay_timer = reader.u8()?;
+        let sound_timer = reader.u8()?;
+
+        let mut gfx = [0; 64 * 32];
+        for (pixels, &packed) in gfx.chunks_mut(8).zip(reader.bytes(64 * 32 / 8)?) {
+            for (bit, pixel) in pixels.iter_mut().enumerate() {
+                *pixel = (packed >> (7 - bit)) & 1;
+            }
+        }
+
+        let keys = reader.u16()?;
+
+        let wait = reader.u8()?;
+        let key = reader.u8()?;
+        if key > 0xF {
+            return Err(StateError::InvalidField("key wait"));
+        }
+        let key_wait = match wait {
+            0 => KeyWait::Idle,
+            1 => KeyWait::Press,
+            2 => KeyWait::Release(key),
+            3 => KeyWait::Done(key),
+            _ => return Err(StateError::InvalidField("key wait")),
+        };
+        let opcode = reader.u16()?;
+
+        //version 1 states predate the seeded random source
+        let random = if version >= 2 {
+            let present = reader.u8()? != 0;
+            let state = reader.u64()?;
+            if present != self.rng.state().is_some() {
+                return Err(StateError::RandomSourceMismatch);
+            }
+            if present { Some(state) } else { None }
+        } else {
+            None
+        };
+
+        //older states only restore at a frame boundary and keep the counters
+        let timing = if version >= 3 {
+            let seed = reader.u64()?;
+            let remainder = reader.u8()? as u32;
+            if remainder >= TIMER_HZ {
+                return Err(StateError::InvalidField("clock remainder"));
+            }
+            //stepping runs at least one instruction per frame, even at speed 0
+            let frame_budget = reader.u32()?;
+            if frame_budget > self.clock.max_per_frame().max(1) {
+                return Err(StateError::InvalidField("frame budget"));
+            }
+            Some((seed, remainder, frame_budget, reader.u64()?, reader.u64()?))
+        } else {
+            None
+        };
+
+        if !reader.is_empty() {
+            return Err(StateError::TrailingBytes(reader.bytes.len() - reader.position));
+        }
+
+        self.memory = memory;
+        self.registers = registers;
+        self.indexing = indexing;
+        self.program_counter = program_counter;
+        self.stack = stack;
+        self.stack_pointer = stack_pointer;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.gfx = gfx;
+        self.keys = keys;
+        self.key_wait = key_wait;
+        self.opcode = opcode;
+        match timing {
+            Some((seed, remainder, frame_budget, frame, cycles)) => {
+                self.seed = seed;
+                self.clock.set_remainder(remainder);
+                self.frame_budget = frame_budget;
+                self.frame = frame;
+                self.cycles = cycles;
+            }
+            None => self.frame_budget = 0,
+        }
+        if let Some(state) = random {
+            self.rng.set_state(state);
+        }
+
+        //the restored sound timer decides whether the tone plays
+        if let Some(ref mut audio) = self.audio {
+            if sound_timer > 0 {
+                audio.start_tone();
+            } else {
+                audio.stop_tone();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, position: 0 }
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], StateError> {
+        let end = self.position + count;
+        if end > self.bytes.len() {
+            return Err(StateError::Truncated);
+        }
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.bytes(2)?;
+        Ok((bytes[0] as u16) << 8 | bytes[1] as u16)
+    }
+
+    fn u32(&mut self) -> Result<u32, StateError> {
+        let bytes = self.bytes(4)?;
+        Ok(bytes.iter().fold(0, |value, &byte| value << 8 | byte as u32))
+    }
+
+    fn u64(&mut self) -> Result<u64, StateError> {
+        let bytes = self.bytes(8)?;
+        Ok(bytes.iter().fold(0, |value, &byte| value << 8 | byte as u64))
+    }
+}
+
+fn push_u16(out: &mut Vec<u8>, value: u16) {
+    out.push((value >> 8) as u8);
+    out.push(value as u8);
+}
+
+fn push_u32(out: &mut Vec<u8>, value: u32) {
+    push_u16(out, (value >> 16) as u16);
+    push_u16(out, value as u16);
+}
+
+fn push_u64(out: &mut Vec<u8>, value: u64) {
+    push_u32(out, (value >> 32) as u32);
+    push_u32(out, value as u32);
+}
+
+/// CRC-32 as used by zip and PNG.
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::Config;
+    use random::RandomSource;
+
+    //loop: RND V0, 0x3F; ADD V1, 1; LD F, V0; DRW V0, V1, 5; JP loop
+    const ROM: [u8; 10] = [0xC0, 0x3F, 0x71, 0x01, 0xF0, 0x29, 0xD0, 0x15, 0x12, 0x00];
+
+    fn machine() -> Chip8 {
+        //not a multiple of 60, so the clock carries a remainder between frames
+        let config = Config { instructions_per_second: 710, seed: 7, ..Config::default() };
+        Chip8::with_config(&ROM, config).unwrap()
+    }
+
+    fn run_frames(chip8: &mut Chip8, frames: usize) {
+        for _ in 0..frames {
+            chip8.run_frame().unwrap();
+        }
+    }
+
+    #[test]
+    fn restoring_continues_exactly_like_the_original() {
+        let mut original = machine();
+        run_frames(&mut original, 7);
+        let state = original.save_state();
+        run_frames(&mut original, 5);
+
+        let mut restored = machine();
+        run_frames(&mut restored, 2);
+        restored.load_state(&state).unwrap();
+        run_frames(&mut restored, 5);
+
+        assert_eq!(restored.save_state(), original.save_state());
+        assert_eq!(restored.cycle_count(), original.cycle_count());
+        assert_eq!(restored.frame_count(), 12);
+        assert_eq!(&restored.get_gfx()[..], &original.get_gfx()[..]);
+    }
+
+    #[test]
+    fn restoring_inside_a_frame_finishes_that_frame() {
+        let mut original = machine();
+        run_frames(&mut original, 3);
+        for _ in 0..4 {
+            original.step_instruction().unwrap();
+        }
+        let state = original.save_state();
+        run_frames(&mut original, 2);
+
+        let mut restored = machine();
+        restored.load_state(&state).unwrap();
+        run_frames(&mut restored, 2);
+
+        assert_eq!(restored.save_state(), original.save_state());
+    }
+
+    #[test]
+    fn corrupted_states_are_rejected_and_leave_the_machine_alone() {
+        let mut chip8 = machine();
+        run_frames(&mut chip8, 3);
+        let state = chip8.save_state();
+        let before = chip8.save_state();
+
+        let mut flipped = state.clone();
+        flipped[HEADER_SIZE + 0x300] ^= 1;
+        assert!(matches!(chip8.load_state(&flipped), Err(StateError::ChecksumMismatch { .. })));
+
+        let mut newer = state.clone();
+        newer[5] = STATE_VERSION as u8 + 1;
+        assert_eq!(chip8.load_state(&newer), Err(StateError::UnsupportedVersion(STATE_VERSION + 1)));
+
+        let mut zero = state.clone();
+        zero[4..6].copy_from_slice(&[0, 0]);
+        assert_eq!(chip8.load_state(&zero), Err(StateError::UnsupportedVersion(0)));
+
+        assert_eq!(chip8.load_state(&state[..state.len() - 1]), Err(StateError::Truncated));
+        assert_eq!(chip8.load_state(b"not a state"), Err(StateError::NotAState));
+
+        assert_eq!(chip8.save_state(), before);
+    }
+
+    /// Rewrites the payload of `state`, fixing up its length and checksum.
+    fn resealed<F: FnOnce(&mut Vec<u8>)>(state: &[u8], change: F) -> Vec<u8> {
+        let mut payload = state[HEADER_SIZE..state.len() - CHECKSUM_SIZE].to_vec();
+        change(&mut payload);
+
+        let mut state = state[..HEADER_SIZE - 4].to_vec();
+        push_u32(&mut state, payload.len() as u32);
+        state.extend_from_slice(&payload);
+        let checksum = crc32(&state);
+        push_u32(&mut state, checksum);
+        state
+    }
+
+    #[test]
+    fn invalid_fields_are_rejected_even_with_a_valid_checksum() {
+        let mut chip8 = machine();
+        let state = chip8.save_state();
+
+        //the PC follows memory, V0..VF and I
+        let pc = MEMORY_SIZE + 16 + 2;
+        let state = resealed(&state, |payload| payload[pc..pc + 2].copy_from_slice(&[0x10, 0x00]));
+
+        assert_eq!(chip8.load_state(&state), Err(StateError::InvalidField("program counter")));
+    }
+
+    #[test]
+    fn frame_budgets_the_clock_can_not_produce_are_rejected() {
+        //the budget is followed by the frame and cycle counters
+        let budget = |value: u32| {
+            resealed(&machine().save_state(), |payload| {
+                let at = payload.len() - 20;
+                payload[at..at + 4].copy_from_slice(&value.to_be_bytes());
+            })
+        };
+
+        //710 instructions per second are 11 or 12 per frame
+        let mut chip8 = machine();
+        chip8.load_state(&budget(12)).unwrap();
+        assert_eq!(chip8.load_state(&budget(13)), Err(StateError::InvalidField("frame budget")));
+        assert_eq!(chip8.load_state(&budget(u32::MAX)), Err(StateError::InvalidField("frame budget")));
+
+        //a stopped clock still steps one instruction at a time
+        let config = Config { instructions_per_second: 0, seed: 7, ..Config::default() };
+        let mut stopped = Chip8::with_config(&ROM, config).unwrap();
+        stopped.load_state(&budget(1)).unwrap();
+        assert_eq!(stopped.load_state(&budget(2)), Err(StateError::InvalidField("frame budget")));
+    }
+
+    #[test]
+    fn trailing_bytes_are_rejected() {
+        let mut chip8 = machine();
+        run_frames(&mut chip8, 2);
+        let before = chip8.save_state();
+
+        let padded = resealed(&before, |payload| payload.extend_from_slice(&[0, 0, 0]));
+        assert_eq!(chip8.load_state(&padded), Err(StateError::TrailingBytes(3)));
+
+        let mut appended = before.clone();
+        appended.push(0);
+        assert_eq!(chip8.load_state(&appended), Err(StateError::TrailingBytes(1)));
+
+        assert_eq!(chip8.save_state(), before);
+    }
+
+    struct Constant;
+
+    impl RandomSource for Constant {
+        fn next_byte(&mut self) -> u8 {
+            4
+        }
+    }
+
+    #[test]
+    fn the_seed_is_restored() {
+        let state = machine().save_state();
+
+        let mut chip8 = Chip8::with_config(&ROM, Config { seed: 99, ..Config::default() }).unwrap();
+        chip8.load_state(&state).unwrap();
+        assert_eq!(chip8.seed(), 7);
+        assert_eq!(chip8.config().seed, 7);
+    }
+
+    #[test]
+    fn random_numbers_that_can_not_be_restored_are_reported() {
+        let state = machine().save_state();
+        let mut custom = machine();
+        custom.set_random_source(Box::new(Constant));
+        assert_eq!(custom.load_state(&state), Err(StateError::RandomSourceMismatch));
+
+        let state = custom.save_state();
+        assert_eq!(machine().load_state(&state), Err(StateError::RandomSourceMismatch));
+
+        //neither side has anything to restore
+        custom.load_state(&state).unwrap();
+    }
+}
@@ -0,0 +1,323 @@
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+
+use chip8::Chip8;
+use debugger::Register;
+use instruction::decode;
+
+/// The registers an instruction can change, besides the PC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Snapshot {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub sp: u8,
+    pub dt: u8,
+    pub st: u8,
+}
+
+impl Snapshot {
+    pub fn of(chip8: &Chip8) -> Snapshot {
+        Snapshot {
+            v: *chip8.registers(),
+            i: chip8.index(),
+            sp: chip8.stack_pointer as u8,
+            dt: chip8.delay_timer(),
+            st: chip8.sound_timer(),
+        }
+    }
+
+    /// The value of a register, None for the PC.
+    pub fn get(&self, register: Register) -> Option<u16> {
+        match register {
+            Register::V(x) => Some(self.v[x as usize & 0xF] as u16),
+            Register::I => Some(self.i),
+            Register::Pc => None,
+            Register::Sp => Some(self.sp as u16),
+            Register::Dt => Some(self.dt as u16),
+            Register::St => Some(self.st as u16),
+        }
+    }
+}
+
+/// One executed instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// Instructions executed before this one.
+    pub cycle: u64,
+    pub frame: u64,
+    pub address: u16,
+    pub opcode: u16,
+    pub before: Snapshot,
+    pub after: Snapshot,
+}
+
+impl TraceEntry {
+    /// The registers the instruction changed with their old and new value.
+    pub fn changes(&self) -> Vec<(Register, u16, u16)> {
+        Register::all()
+            .into_iter()
+            .filter_map(|register| match (self.before.get(register), self.after.get(register)) {
+                (Some(before), Some(after)) if before != after => Some((register, before, after)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Receives every instruction the machine executes, see
+/// `Chip8::set_trace_sink`.
+pub trait TraceSink {
+    fn record(&mut self, entry: &TraceEntry);
+}
+
+/// Lets a host keep a handle on a sink that is owned by the emulator, e.g.
+/// to flush a `TraceWriter` when done.
+impl<S: TraceSink + ?Sized> TraceSink for Arc<Mutex<S>> {
+    fn record(&mut self, entry: &TraceEntry) {
+        if let Ok(mut sink) = self.lock() {
+            sink.record(entry);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// Aligned columns with the changed registers, for reading.
+    Text,
+    /// One JSON object per instruction.
+    JsonLines,
+    /// A header and one row with all registers per instruction, for
+    /// diffing against other emulators.
+    Csv,
+}
+
+/// Writes the entries that pass the address and cycle filters.
+///
+/// Write errors stop the trace, `finish` reports them.
+pub struct TraceWriter<W: Write> {
+    out: W,
+    format: TraceFormat,
+    addresses: Option<RangeInclusive<u16>>,
+    cycles: Option<RangeInclusive<u64>>,
+    started: bool,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(out: W, format: TraceFormat) -> TraceWriter<W> {
+        TraceWriter { out, format, addresses: None, cycles: None, started: false, error: None }
+    }
+
+    /// Only traces instructions fetched from these addresses.
+    pub fn with_addresses(mut self, addresses: RangeInclusive<u16>) -> TraceWriter<W> {
+        self.addresses = Some(addresses);
+        self
+    }
+
+    /// Only traces these cycles, counted from power on.
+    pub fn with_cycles(mut self, cycles: RangeInclusive<u64>) -> TraceWriter<W> {
+        self.cycles = Some(cycles);
+        self
+    }
+
+    /// Flushes the output, fails with the first write error if any.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.out.flush()
+    }
+
+    fn write(&mut self, entry: &TraceEntry) -> io::Result<()> {
+        let asm = decode(entry.opcode).to_string();
+
+        match self.format {
+            TraceFormat::Text => {
+                let changes: Vec<String> = entry
+                    .changes()
+                    .iter()
+                    .map(|&(register, before, after)| match register {
+                        Register::I => format!("I {:03X}->{:03X}", before, after),
+                        _ => format!("{} {:02X}->{:02X}", register, before, after),
+                    })
+                    .collect();
+                let line = format!(
+                    "{:>8} {:>6}  {:03X}  {:04X}  {:<20} {}",
+                    entry.cycle,
+                    entry.frame,
+                    entry.address,
+                    entry.opcode,
+                    asm,
+                    changes.join("  ")
+                );
+                writeln!(self.out, "{}", line.trim_end())
+            }
+            TraceFormat::JsonLines => {
+                let changes: Vec<String> = entry
+                    .changes()
+                    .iter()
+                    .map(|&(register, before, after)| format!("\"{}\":[{},{}]", register, before, after))
+                    .collect();
+                writeln!(
+                    self.out,
+                    "{{\"cycle\":{},\"frame\":{},\"pc\":{},\"opcode\":{},\"asm\":\"{}\",\"changes\":{{{}}}}}",
+                    entry.cycle,
+                    entry.frame,
+                    entry.address,
+                    entry.opcode,
+                    asm,
+                    changes.join(",")
+                )
+            }
+            TraceFormat::Csv => {
+                if !self.started {
+                    let registers: Vec<String> = Register::all()
+                        .into_iter()
+                        .filter(|&register| register != Register::Pc)
+                        .map(|register| register.to_string().to_lowercase())
+                        .collect();
+                    writeln!(self.out, "cycle,frame,pc,opcode,asm,{}", registers.join(","))?;
+                }
+                let values: Vec<String> = Register::all()
+                    .into_iter()
+                    .filter_map(|register| entry.after.get(register).map(|value| format!("0x{:02X}", value)))
+                    .collect();
+                writeln!(
+                    self.out,
+                    "{},{},0x{:03X},0x{:04X},\"{}\",{}",
+                    entry.cycle,
+                    entry.frame,
+                    entry.address,
+                    entry.opcode,
+                    asm,
+                    values.join(",")
+                )
+            }
+        }
+    }
+}
+
+impl<W: Write> TraceSink for TraceWriter<W> {
+    fn record(&mut self, entry: &TraceEntry) {
+        let traced = self.addresses.as_ref().is_none_or(|range| range.contains(&entry.address))
+            && self.cycles.as_ref().is_none_or(|range| range.contains(&entry.cycle));
+        if !traced || self.error.is_some() {
+            return;
+        }
+
+        let result = self.write(entry);
+        self.started = true;
+        if let Err(err) = result {
+            self.error = Some(err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8::Chip8;
+
+    /// ADD V1, V0 then ADD I, V0.
+    fn entries() -> [TraceEntry; 2] {
+        let before = Snapshot { v: [0; 16], i: 0x2A0, sp: 1, dt: 0, st: 0 };
+        let mut added = before;
+        added.v[1] = 0x15;
+        added.v[0xF] = 1;
+        let mut indexed = added;
+        indexed.i = 0x2B5;
+
+        [
+            TraceEntry { cycle: 13, frame: 1, address: 0x204, opcode: 0x8104, before, after: added },
+            TraceEntry { cycle: 14, frame: 1, address: 0x206, opcode: 0xF01E, before: added, after: indexed },
+        ]
+    }
+
+    fn written(mut writer: TraceWriter<Vec<u8>>, entries: &[TraceEntry]) -> Vec<String> {
+        for entry in entries {
+            writer.record(entry);
+        }
+        writer.finish().unwrap();
+        String::from_utf8(writer.out).unwrap().lines().map(str::to_string).collect()
+    }
+
+    fn traced(format: TraceFormat) -> Vec<String> {
+        written(TraceWriter::new(Vec::new(), format), &entries())
+    }
+
+    #[test]
+    fn text_lists_the_changed_registers() {
+        assert_eq!(traced(TraceFormat::Text), [
+            "      13      1  204  8104  ADD V1, V0           V1 00->15  VF 00->01",
+            "      14      1  206  F01E  ADD I, V0            I 2A0->2B5",
+        ]);
+    }
+
+    #[test]
+    fn json_lines_have_one_object_per_instruction() {
+        assert_eq!(traced(TraceFormat::JsonLines), [
+            r#"{"cycle":13,"frame":1,"pc":516,"opcode":33028,"asm":"ADD V1, V0","changes":{"V1":[0,21],"VF":[0,1]}}"#,
+            r#"{"cycle":14,"frame":1,"pc":518,"opcode":61470,"asm":"ADD I, V0","changes":{"I":[672,693]}}"#,
+        ]);
+    }
+
+    #[test]
+    fn csv_has_a_header_and_all_registers() {
+        let zeros = "0x00,".repeat(13);
+        assert_eq!(traced(TraceFormat::Csv), [
+            "cycle,frame,pc,opcode,asm,v0,v1,v2,v3,v4,v5,v6,v7,v8,v9,va,vb,vc,vd,ve,vf,i,sp,dt,st".to_string(),
+            format!("13,1,0x204,0x8104,\"ADD V1, V0\",0x00,0x15,{}0x01,0x2A0,0x01,0x00,0x00", zeros),
+            format!("14,1,0x206,0xF01E,\"ADD I, V0\",0x00,0x15,{}0x01,0x2B5,0x01,0x00,0x00", zeros),
+        ]);
+    }
+
+    /// The cycles of the instructions the writer lets through, out of ten
+    /// at 0x200, 0x202 and so on.
+    fn filtered(writer: TraceWriter<Vec<u8>>) -> Vec<u64> {
+        let entries: Vec<TraceEntry> = (0..10)
+            .map(|cycle| TraceEntry { cycle, address: 0x200 + 2 * cycle as u16, ..entries()[0] })
+            .collect();
+        written(writer, &entries).iter().map(|line| line.split_whitespace().next().unwrap().parse().unwrap()).collect()
+    }
+
+    fn writer() -> TraceWriter<Vec<u8>> {
+        TraceWriter::new(Vec::new(), TraceFormat::Text)
+    }
+
+    #[test]
+    fn filters_by_address_and_cycle() {
+        assert_eq!(filtered(writer()), (0..10).collect::<Vec<_>>());
+        assert_eq!(filtered(writer().with_addresses(0x204..=0x208)), [2, 3, 4]);
+        assert_eq!(filtered(writer().with_addresses(0x20E..=0xFFFF)), [7, 8, 9]);
+        assert_eq!(filtered(writer().with_cycles(3..=5)), [3, 4, 5]);
+        assert_eq!(filtered(writer().with_cycles(8..=u64::MAX)), [8, 9]);
+        assert_eq!(filtered(writer().with_addresses(0x200..=0x20A).with_cycles(4..=u64::MAX)), [4, 5]);
+        assert_eq!(filtered(writer().with_cycles(20..=30)), []);
+    }
+
+    #[test]
+    fn csv_header_is_written_with_the_first_traced_line() {
+        let writer = TraceWriter::new(Vec::new(), TraceFormat::Csv).with_cycles(20..=30);
+        assert!(written(writer, &entries()).is_empty());
+    }
+
+    #[test]
+    fn machine_reports_every_instruction() {
+        //LD V0, 5; ADD V0, 1; JP 0x202
+        let mut chip8 = Chip8::from_rom(&[0x60, 0x05, 0x70, 0x01, 0x12, 0x02]).unwrap();
+        let sink = Arc::new(Mutex::new(writer()));
+        chip8.set_trace_sink(Some(Box::new(sink.clone())));
+        for _ in 0..4 {
+            chip8.step_instruction().unwrap();
+        }
+
+        let mut writer = sink.lock().unwrap();
+        writer.finish().unwrap();
+        let text = String::from_utf8(writer.out.clone()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("       0      0  200  6005") && lines[0].ends_with("V0 00->05"));
+        assert!(lines[3].starts_with("       3      0  202  7001") && lines[3].ends_with("V0 06->07"));
+    }
+}